@@ -0,0 +1,56 @@
+// Generated test data for the benchmarks.
+
+pub fn text(len: usize) -> Vec<u8> {
+    let words = ["the", "quick", "brown", "fox", "jumps", "over", "lazy", "dog",
+        "compression", "block", "frame", "literal", "match", "offset"];
+    let mut data: Vec<u8> = Vec::new();
+    let mut x: u64 = 1;
+    while data.len() < len {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        data.extend_from_slice(words[(x % words.len() as u64) as usize].as_bytes());
+        data.push(if x.is_multiple_of(11) { b'\n' } else { b' ' });
+    }
+    data.truncate(len);
+    return data;
+}
+
+// Short runs of repeated bytes and byte pairs, as in images or tables.
+pub fn runs(len: usize) -> Vec<u8> {
+    let mut data: Vec<u8> = Vec::new();
+    let mut x: u64 = 1;
+    while data.len() < len {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        let run = (x >> 8) as usize % 40;
+        for i in 0..run {
+            data.push(if x.is_multiple_of(3) { x as u8 } else { (x >> (8 * (i % 2))) as u8 });
+        }
+        data.push((x >> 32) as u8);
+    }
+    data.truncate(len);
+    return data;
+}
+
+// Telemetry records: lines with a timestamp, some fields and random values.
+pub fn records(len: usize) -> Vec<u8> {
+    let hosts = ["web-1", "web-2", "db-1", "cache-3"];
+    let mut data: Vec<u8> = Vec::new();
+    let mut x: u64 = 1;
+    let mut time: u64 = 1_700_000_000_000;
+    while data.len() < len {
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        time += x % 50;
+        let host = hosts[(x >> 8) as usize % hosts.len()];
+        let cpu = (x >> 16) % 1000;
+        let mem = (x >> 26) % 10_000_000;
+        let status = if x.is_multiple_of(97) { "error" } else { "ok" };
+        data.extend_from_slice(format!("{time} host={host} cpu={cpu} mem={mem} status={status}\n").as_bytes());
+    }
+    data.truncate(len);
+    return data;
+}
@@ -0,0 +1,41 @@
+// Block compression throughput, for the fast levels. Run with "cargo bench".
+
+#![allow(clippy::needless_return)]
+#![allow(clippy::slow_vector_initialization)]
+
+use std::time::Instant;
+
+use lz4_simple::Compress;
+use lz4_simple::compress_bound;
+
+mod common;
+
+fn bench(name: &str, data: &[u8], acceleration: usize) {
+    let mut comp = Compress::new(data.len(), 1);
+    comp.set_acceleration(acceleration);
+    let mut block: Vec<u8> = Vec::new();
+    block.resize(compress_bound(data.len()), 0);
+    let mut end = 0;
+    let mut best = f64::MAX;
+    for _ in 0..5 {
+        let start = Instant::now();
+        for _ in 0..10 {
+            end = comp.compress_block(data, &mut block, 0).unwrap();
+        }
+        best = best.min(start.elapsed().as_secs_f64() / 10.0);
+    }
+    let ratio = data.len() as f64 / end as f64;
+    println!("{name:>8} fast {acceleration:2}: ratio {ratio:5.2}, {:7.0} MB/s", data.len() as f64 / best / 1e6);
+}
+
+fn main() {
+    let len = 4 * 1024 * 1024;
+    let text = common::text(len);
+    let runs = common::runs(len);
+    let records = common::records(len);
+    for acceleration in [1, 2, 4, 8, 16, 32] {
+        bench("text", &text, acceleration);
+        bench("runs", &runs, acceleration);
+        bench("records", &records, acceleration);
+    }
+}
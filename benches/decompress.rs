@@ -0,0 +1,45 @@
+// Block decompression throughput. Run with "cargo bench".
+
+#![allow(clippy::needless_return)]
+#![allow(clippy::slow_vector_initialization)]
+
+use std::time::Instant;
+
+use lz4_simple::Compress;
+use lz4_simple::compress_bound;
+use lz4_simple::decompress_block;
+
+mod common;
+
+fn bench(name: &str, data: &[u8], level: usize) {
+    let mut block: Vec<u8> = Vec::new();
+    block.resize(compress_bound(data.len()), 0);
+    let end = Compress::new(data.len(), level).compress_block(data, &mut block, 0).unwrap();
+    let block = &block[0..end];
+    let mut out_block: Vec<u8> = Vec::new();
+    out_block.resize(data.len(), 0);
+    let mut best = f64::MAX;
+    for _ in 0..5 {
+        let start = Instant::now();
+        for _ in 0..20 {
+            let len = decompress_block(block, &mut out_block, 0).unwrap();
+            assert_eq!(len, data.len());
+        }
+        best = best.min(start.elapsed().as_secs_f64() / 20.0);
+    }
+    assert_eq!(out_block, data);
+    let ratio = data.len() as f64 / block.len() as f64;
+    println!("{name:>8} level {level}: ratio {ratio:5.2}, {:7.0} MB/s", data.len() as f64 / best / 1e6);
+}
+
+fn main() {
+    let len = 4 * 1024 * 1024;
+    let text = common::text(len);
+    let runs = common::runs(len);
+    let records = common::records(len);
+    for level in [1, 9] {
+        bench("text", &text, level);
+        bench("runs", &runs, level);
+        bench("records", &records, level);
+    }
+}
@@ -1,15 +1,16 @@
+use std::cmp::max;
 use std::cmp::min;
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::io::BufWriter;
 use std::fs::File;
-use std::io::Error;
 
-use crate::xxhash32::read_vec_u32_le;
+use crate::xxhash32::read_u32_le;
 use crate::xxhash32::read_u64_le;
-use crate::xxhash32::write_vec_u32_le;
+use crate::xxhash32::write_u32_le;
+use crate::xxhash32::write_u64_le;
 use crate::xxhash32::XXHash32;
-use crate::xxhash32::error;
+use crate::error::Error;
 use crate::xxhash32::read_fully;
 
 use std::cmp::Ordering;
@@ -19,59 +20,318 @@ pub fn compress_stream(level: usize) -> Result<usize, Error> {
 }
 
 pub fn compress_file(input_file_name: &str, output_file_name: &str, level: usize) -> Result<usize, Error> {
+    return compress_file_with_options(input_file_name, output_file_name, &CompressOptions::new(level));
+}
+
+/// Compress a file. The content size is stored in the frame header,
+/// unless the input is not a regular file.
+pub fn compress_file_with_options(input_file_name: &str, output_file_name: &str, options: &CompressOptions) -> Result<usize, Error> {
     let in_file = File::open(input_file_name)?;
+    let metadata = in_file.metadata()?;
     let out_file = File::create(output_file_name)?;
-    return compress(in_file, out_file, level);
+    let mut options = options.clone();
+    if metadata.is_file() {
+        options.content_size = Some(metadata.len());
+    }
+    return compress_with_options(in_file, out_file, &options);
+}
+
+/// Options for compressing a frame.
+#[derive(Clone)]
+pub struct CompressOptions {
+    /// The compression level, from 1 (fast) to 9 (slow).
+    pub level: usize,
+    /// The acceleration for level 1, like "lz4 --fast=N": values above 1
+    /// (the default) compress less, and are faster unless the data has
+    /// many matches.
+    pub acceleration: usize,
+    /// Whether to append the XXHash32 checksum of the uncompressed data
+    /// (enabled by default, like in the "lz4" command line tool).
+    pub content_checksum: bool,
+    /// Whether to append the XXHash32 checksum of each block.
+    pub block_checksum: bool,
+    /// The size of the uncompressed data, if known. It is stored in the
+    /// frame header, and compression fails if the data has a different size.
+    pub content_size: Option<u64>,
+    /// Whether matches may refer to the previous block (linked blocks).
+    /// This improves the compression ratio for small blocks.
+    pub linked_blocks: bool,
+    /// The block maximum size id: 4 (64 KB), 5 (256 KB), 6 (1 MB),
+    /// or 7 (4 MB, the default).
+    pub block_max_size: u8,
+    /// The dictionary to use (only the last 64 KB are used).
+    pub dictionary: Option<Vec<u8>>,
+    /// The dictionary id to store in the frame header.
+    pub dictionary_id: Option<u32>,
+    /// Whether to use the legacy frame format, with blocks of 8 MB.
+    /// This format does not support checksums, the content size,
+    /// linked blocks or dictionaries, so those options are ignored.
+    pub legacy_format: bool,
+}
+
+impl CompressOptions {
+    pub fn new(level: usize) -> CompressOptions {
+        CompressOptions {
+            level,
+            acceleration: 1,
+            content_checksum: true,
+            block_checksum: false,
+            content_size: None,
+            linked_blocks: false,
+            block_max_size: 7,
+            dictionary: None,
+            dictionary_id: None,
+            legacy_format: false,
+        }
+    }
 }
 
 pub fn compress<R: Read, W: Write>(read: R, write: W, level: usize) -> Result<usize, Error> {
+    return compress_with_options(read, write, &CompressOptions::new(level));
+}
+
+pub fn compress_with_options<R: Read, W: Write>(read: R, write: W, options: &CompressOptions) -> Result<usize, Error> {
+    let mut frame = FrameState::new(options, usize::MAX)?;
     let mut reader = BufReader::new(read);
-    let mut header: Vec<u8> = Vec::new();
-    header.resize(7, 0);
-    let magic = 0x184D2204;
-    write_vec_u32_le(&mut header, 0, magic);
-    header[4] = (1 << 6) | (1 << 5);
-    let bd = 7 << 4;
-    header[5] = bd;
-    let mut hash = XXHash32::new(0);
-    let xxhash = (hash.update(&header, 4, 2)? >> 8) & 0xff;
-    header[6] = xxhash as u8;
     let mut writer = BufWriter::new(write);
-    writer.write_all(&header)?;
-    let mut block: Vec<u8> = Vec::new();
-    let block_size = 4 * 1024 * 1024;
-    block.resize(block_size, 0);
-    let mut out_block: Vec<u8> = Vec::new();
-    out_block.resize(5 * 1024 * 1024, 0);
-    let mut comp = Compress::new(5 * 1024 * 1024, level);
-    let mut total_size = 0;
+    writer.write_all(frame.header())?;
     loop {
-        let read = read_fully(&mut reader,&mut block[0..block_size])?;
+        let read = read_fully(&mut reader, frame.input())?;
         if read == 0 {
             break;
         }
-        total_size += read;
-        let end = comp.compress_block(&block, read,&mut out_block, 4)?;
-        if end >= read {
+        writer.write_all(frame.compress_block(read)?)?;
+    }
+    writer.write_all(frame.end()?)?;
+    drop(reader);
+    writer.flush()?;
+    drop(writer);
+    return Ok(frame.total_size);
+}
+
+/// Compress the data into a frame. The content size is stored in the frame
+/// header, unless the legacy format is used.
+pub fn compress_to_vec(data: &[u8], options: &CompressOptions) -> Result<Vec<u8>, Error> {
+    let mut options = options.clone();
+    if options.content_size.is_none() && !options.legacy_format {
+        options.content_size = Some(data.len() as u64);
+    }
+    let mut frame = FrameState::new(&options, max(data.len(), 1))?;
+    // in the worst case, all blocks are stored, with block size and checksum
+    let block_count = data.len().div_ceil(frame.block_size);
+    let mut output: Vec<u8> = Vec::with_capacity(frame.header_len + data.len() + 8 * block_count + 8);
+    output.extend_from_slice(frame.header());
+    let mut pos = 0;
+    while pos < data.len() {
+        let input = frame.input();
+        let len = min(input.len(), data.len() - pos);
+        input[0..len].copy_from_slice(&data[pos..pos + len]);
+        output.extend_from_slice(frame.compress_block(len)?);
+        pos += len;
+    }
+    output.extend_from_slice(frame.end()?);
+    return Ok(output);
+}
+
+/// The maximum size of a compressed block, for the given input length.
+pub fn compress_bound(len: usize) -> usize {
+    return len + len / 255 + 16;
+}
+
+/// Compress a block with level 1 into the output buffer, which must be at
+/// least compress_bound(input.len()) bytes. Returns the compressed size.
+pub fn compress_block_into(input: &[u8], output: &mut [u8]) -> Result<usize, Error> {
+    let mut comp = Compress::new(input.len(), 1);
+    return comp.compress_block(input, output, 0);
+}
+
+// The state of compressing one frame, block by block.
+pub(crate) struct FrameState {
+    // the legacy frame format: the magic number, followed by blocks of up
+    // to 8 MB, each one with the compressed size; blocks are always
+    // compressed, and there is no end mark
+    legacy: bool,
+    content_checksum: bool,
+    block_checksum: bool,
+    content_size: Option<u64>,
+    linked_blocks: bool,
+    // magic, flags, block descriptor, content size, dictionary id, header checksum
+    header: Vec<u8>,
+    pub(crate) header_len: usize,
+    // with linked blocks, the last 64 KB of the previous block
+    // are kept at the start of the block; the same is done for the dictionary
+    history_size: usize,
+    history_len: usize,
+    pub(crate) block_size: usize,
+    block: Vec<u8>,
+    // block size, compressed data (which can be a bit larger than the input),
+    // block checksum
+    out_block: Vec<u8>,
+    comp: Compress,
+    content_hash: XXHash32,
+    pub(crate) total_size: usize,
+}
+
+impl FrameState {
+    // The buffers are large enough for blocks of up to max_len bytes.
+    pub(crate) fn new(options: &CompressOptions, max_len: usize) -> Result<FrameState, Error> {
+        let mut header: Vec<u8> = Vec::new();
+        header.resize(19, 0);
+        let mut header_len = 4;
+        if options.legacy_format {
+            write_u32_le(&mut header, 0, 0x184C2102);
+            return Ok(FrameState::with_buffers(options, header, header_len, min(8 * 1024 * 1024, max_len), 0));
+        }
+        let block_max_size = options.block_max_size;
+        if block_max_size < 4 || block_max_size > 7 {
+            return Err(Error::Unsupported(format!("block max size {block_max_size}")));
+        }
+        let magic = 0x184D2204;
+        write_u32_le(&mut header, 0, magic);
+        header[4] = 1 << 6;
+        if !options.linked_blocks {
+            header[4] |= 1 << 5;
+        }
+        if options.block_checksum {
+            header[4] |= 1 << 4;
+        }
+        if options.content_checksum {
+            header[4] |= 1 << 2;
+        }
+        let bd = block_max_size << 4;
+        header[5] = bd;
+        header_len += 2;
+        if let Some(content_size) = options.content_size {
+            header[4] |= 1 << 3;
+            write_u64_le(&mut header, header_len, content_size);
+            header_len += 8;
+        }
+        if let Some(dictionary_id) = options.dictionary_id {
+            header[4] |= 1;
+            write_u32_le(&mut header, header_len, dictionary_id);
+            header_len += 4;
+        }
+        let mut hash = XXHash32::new(0);
+        let xxhash = (hash.update(&header[4..header_len]) >> 8) & 0xff;
+        header[header_len] = xxhash as u8;
+        header_len += 1;
+        let history_size = if options.linked_blocks || options.dictionary.is_some() { 64 * 1024 } else { 0 };
+        let block_size = min(1 << (8 + 2 * block_max_size), max_len);
+        let mut frame = FrameState::with_buffers(options, header, header_len, block_size, history_size);
+        if let Some(dictionary) = &options.dictionary {
+            let dict = &dictionary[dictionary.len().saturating_sub(history_size)..];
+            frame.block[0..dict.len()].copy_from_slice(dict);
+            frame.history_len = dict.len();
+        }
+        return Ok(frame);
+    }
+
+    fn with_buffers(options: &CompressOptions, header: Vec<u8>, header_len: usize, block_size: usize, history_size: usize) -> FrameState {
+        let legacy = options.legacy_format;
+        let mut block: Vec<u8> = Vec::new();
+        block.resize(history_size + block_size, 0);
+        let mut out_block: Vec<u8> = Vec::new();
+        out_block.resize(4 + compress_bound(block_size) + 4, 0);
+        let mut comp = Compress::new(history_size + block_size, options.level);
+        comp.set_acceleration(options.acceleration);
+        return FrameState {
+            legacy,
+            content_checksum: options.content_checksum && !legacy,
+            block_checksum: options.block_checksum && !legacy,
+            content_size: if legacy { None } else { options.content_size },
+            linked_blocks: options.linked_blocks && !legacy,
+            header,
+            header_len,
+            history_size,
+            history_len: 0,
+            block_size,
+            block,
+            out_block,
+            comp,
+            content_hash: XXHash32::new(0),
+            total_size: 0,
+        };
+    }
+
+    pub(crate) fn header(&self) -> &[u8] {
+        return &self.header[0..self.header_len];
+    }
+
+    // The buffer for the data of the next block.
+    pub(crate) fn input(&mut self) -> &mut [u8] {
+        return &mut self.block[self.history_len..self.history_len + self.block_size];
+    }
+
+    // Compress the first len bytes of the input buffer. Returns the block,
+    // including the block size and checksum.
+    pub(crate) fn compress_block(&mut self, len: usize) -> Result<&[u8], Error> {
+        self.total_size += len;
+        let history_len = self.history_len;
+        let block_end = history_len + len;
+        if self.content_checksum {
+            self.content_hash.update(&self.block[history_len..block_end]);
+        }
+        let mut end = self.comp.compress_block_with_history(&self.block[0..block_end], history_len, &mut self.out_block, 4)?;
+        if end >= len && !self.legacy {
             // can not compress
             let mut write_block_size = 1 << 31;
-            write_block_size |= read;
-            write_vec_u32_le(&mut out_block, 0, write_block_size as u32);
-            out_block[4..4 + read].copy_from_slice(&block[0..read]);
-            writer.write_all(&out_block[0..read + 4])?;
+            write_block_size |= len;
+            write_u32_le(&mut self.out_block, 0, write_block_size as u32);
+            self.out_block[4..4 + len].copy_from_slice(&self.block[history_len..block_end]);
+            end = len + 4;
         } else {
-            write_vec_u32_le(&mut out_block, 0, (end - 4) as u32);
-            writer.write_all(&out_block[0..end])?;
+            write_u32_le(&mut self.out_block, 0, (end - 4) as u32);
+        }
+        if self.linked_blocks {
+            let keep = min(block_end, self.history_size);
+            self.block.copy_within(block_end - keep..block_end, 0);
+            self.history_len = keep;
         }
+        if self.block_checksum {
+            let mut block_hash = XXHash32::new(0);
+            let checksum = block_hash.update(&self.out_block[4..end]);
+            write_u32_le(&mut self.out_block, end, checksum);
+            end += 4;
+        }
+        return Ok(&self.out_block[0..end]);
+    }
+
+    // Verify the content size. Returns the end mark and content checksum.
+    pub(crate) fn end(&mut self) -> Result<&[u8], Error> {
+        if self.legacy {
+            return Ok(&[]);
+        }
+        if let Some(content_size) = self.content_size {
+            if self.total_size as u64 != content_size {
+                return Err(Error::ContentSize { expected: content_size, actual: self.total_size as u64 });
+            }
+        }
+        write_u32_le(&mut self.out_block, 0, 0);
+        let mut end = 4;
+        if self.content_checksum {
+            write_u32_le(&mut self.out_block, 4, self.content_hash.digest());
+            end += 4;
+        }
+        return Ok(&self.out_block[0..end]);
     }
-    write_vec_u32_le(&mut out_block, 0, 0);
-    writer.write_all(&out_block[0..4])?;
-    drop(block);
-    drop(reader);
-    writer.flush()?;
-    drop(out_block);
-    drop(writer);
-    return Ok(total_size);
+}
+
+/// Write a skippable frame with the given data. The id (0 to 15) is
+/// added to the magic number 0x184D2A50. Returns the number of data bytes.
+pub fn write_skippable_frame<W: Write>(mut write: W, id: u32, data: &[u8]) -> Result<usize, Error> {
+    if id > 15 {
+        return Err(Error::Unsupported(format!("skippable frame id {id}")));
+    }
+    if data.len() > u32::MAX as usize {
+        return Err(Error::InvalidInput("Skippable frame too large"));
+    }
+    let mut header: Vec<u8> = Vec::new();
+    header.resize(8, 0);
+    write_u32_le(&mut header, 0, 0x184D2A50 + id);
+    write_u32_le(&mut header, 4, data.len() as u32);
+    write.write_all(&header)?;
+    write.write_all(data)?;
+    return Ok(data.len());
 }
 
 fn hash64(x: u64) -> u64 {
@@ -80,18 +340,18 @@ fn hash64(x: u64) -> u64 {
     return b ^ (b >> 33);
 }
 
-fn hash(data: &Vec<u8>, pos: usize, mask: usize) -> usize {
-    let x = read_vec_u32_le(data, pos);
-    return hash64(x as u64) as usize & mask as usize;
+fn hash(data: &[u8], pos: usize, mask: usize) -> usize {
+    let x = read_u32_le(data, pos);
+    return hash64(x as u64) as usize & mask;
 }
 
-fn hash5(data: &Vec<u8>, pos: usize) -> usize {
+fn hash5(data: &[u8], pos: usize, bits: u32) -> usize {
     let x: u64 = read_u64_le(data, pos);
     let prime5bytes: u64 = 889523592379;
-    return ((x << 24).wrapping_mul(prime5bytes) >> (64 - 12)) as usize;
+    return ((x << 24).wrapping_mul(prime5bytes) >> (64 - bits)) as usize;
 }
 
-fn compare_at(data: &Vec<u8>, a: &usize, b: &usize) -> Ordering {
+fn compare_at(data: &[u8], a: &usize, b: &usize) -> Ordering {
     let mut max = min(100000, data.len() - a);
     max = min(max, data.len() - b);
     for i in 0..max {
@@ -100,12 +360,12 @@ fn compare_at(data: &Vec<u8>, a: &usize, b: &usize) -> Ordering {
             return r;
         }
     }
-    return a.cmp(&b);
+    return a.cmp(b);
 }
 
-fn run_len_count(a: &Vec<u8>, a_len: usize, ai: usize, bi: usize) -> usize {
+fn run_len_count(a: &[u8], ai: usize, bi: usize) -> usize {
     let mut run_len = 0;
-    while ai + run_len < a_len - 24 {
+    while ai + run_len + 24 < a.len() {
         let ax =  read_u64_le(a, ai + run_len);
         let bx =  read_u64_le(a, bi + run_len);
         let diff = ax ^ bx;
@@ -116,17 +376,17 @@ fn run_len_count(a: &Vec<u8>, a_len: usize, ai: usize, bi: usize) -> usize {
             return run_len;
         }
     }
-    while ai + run_len < a_len - 16 &&
+    while ai + run_len + 16 < a.len() &&
         a[ai + run_len] == a[bi + run_len] {
         run_len += 1;
     }
     return run_len;
 }
 
-fn run_len_backwards(a: &Vec<u8>, a_len: usize, ai: usize, bi: usize, min: usize) -> usize {
-    //return run_len_count(a, a_len, ai, bi);
-    if ai + min + 1 >= a_len - 32 {
-        return run_len_count(a, a_len, ai, bi);
+fn run_len_backwards(a: &[u8], ai: usize, bi: usize, min: usize) -> usize {
+    //return run_len_count(a, ai, bi);
+    if ai + min + 33 >= a.len() {
+        return run_len_count(a, ai, bi);
     }
     let mut run_len = min + 1;
     while run_len != 0 {
@@ -139,93 +399,304 @@ fn run_len_backwards(a: &Vec<u8>, a_len: usize, ai: usize, bi: usize, min: usize
         return 0;
     }
     run_len = min + 1;
-    while ai + run_len < a_len - 16 &&
+    while ai + run_len + 16 < a.len() &&
         a[ai + run_len] == a[bi + run_len] {
         run_len += 1;
     }
     return run_len;
 }
 
-struct Compress {
+/// A block compressor. The hash and chain tables are kept between calls,
+/// so that the same instance can be used for many blocks.
+pub struct Compress {
+    // the entries are positions plus base; entries below base are from
+    // previous blocks, and are ignored
     hash_tab: Vec<u32>,
+    // the previous position with the same hash, indexed by position modulo
+    // the chain size; at most 64 KB are needed, as matches can not be
+    // further away
     chain: Vec<u32>,
+    base: usize,
     len: usize,
+    // the number of bytes of the dictionary that are used
+    dict_window: usize,
     stop_at_match_len: usize,
     max_search: usize,
+    hash_bits: u32,
+    // the hash bits that fit in the memory limit
+    max_hash_bits: u32,
     mask: usize,
+    chain_mask: usize,
     step: usize,
-    level: usize
+    level: usize,
+    acceleration: usize,
+    // dictionary followed by the input, for compress_block_with_dictionary
+    dict_buffer: Vec<u8>,
+    // the matches and costs per position, for level 9
+    match_offsets: Vec<u32>,
+    match_lens: Vec<u32>,
+    indexes: Vec<usize>,
+    costs: Vec<usize>,
 }
 
 impl Compress {
+    /// Create a compressor for blocks of up to len bytes,
+    /// with level 1 (fast) to 9 (slow).
     pub fn new(len: usize, level: usize) -> Compress {
-        let mask = (1 << (12 + level)) - 1;
+        return Compress::with_memory_limit(len, level, usize::MAX);
+    }
+
+    /// Create a compressor for blocks of up to len bytes, using at most
+    /// max_memory bytes (but at least 4 KB at level 1, and 8 KB at levels
+    /// 2 to 8), even if a dictionary is used. The hash tables are made
+    /// smaller to fit, which lowers the compression ratio. The memory that
+    /// is left is used for the dictionary: only the part that fits is used.
+    /// Level 9 needs 24 bytes per input byte; if that does not fit, level 8
+    /// is used.
+    pub fn with_memory_limit(len: usize, level: usize, max_memory: usize) -> Compress {
+        let mut level = level;
+        if level >= 9 && len.saturating_mul(24) > max_memory {
+            level = 8;
+        }
+        // the memory per position for level 9, including the dictionary
+        let pos_size = if level >= 9 { 24 } else { 0 };
+        // a dictionary needs a buffer for the dictionary and the input
+        let dict_size = |window: usize| -> usize {
+            return len + window * (1 + pos_size);
+        };
+        let mut hash_bits = if level == 1 { 12 } else if level < 9 { 12 + level as u32 } else { 0 };
+        let mut chain_bits = if level > 1 && level < 9 { min(len.next_power_of_two(), 0x10000).trailing_zeros() } else { 0 };
+        let mut memory;
+        loop {
+            let hash_len = if level < 9 { 1 << hash_bits } else { 0 };
+            let chain_len = if level > 1 && level < 9 { 1 << chain_bits } else { 0 };
+            memory = 4 * (hash_len + chain_len) + len * pos_size;
+            if memory <= max_memory {
+                break;
+            }
+            // halve the larger table
+            if hash_bits >= chain_bits && hash_bits > 10 {
+                hash_bits -= 1;
+            } else if chain_bits > 10 {
+                chain_bits -= 1;
+            } else {
+                break;
+            }
+        }
+        // the part of the dictionary that fits in the memory that is left
+        let mut dict_window = 0x10000;
+        while dict_window > 0 && memory.saturating_add(dict_size(dict_window)) > max_memory {
+            dict_window = if dict_window > 1024 { dict_window / 2 } else { 0 };
+        }
         let mut hash: Vec<u32> = Vec::new();
-        hash.resize(mask + 1, u32::MAX);
+        if level < 9 {
+            hash.resize(1 << hash_bits, 0);
+        }
         let mut chain: Vec<u32> = Vec::new();
-        chain.resize(len, u32::MAX);
+        if level > 1 && level < 9 {
+            chain.resize(1 << chain_bits, 0);
+        }
         let stop_at_match_len = level * 10;
         let max_search = 1 << level;
         let step = if level == 1 { 4 } else { 1 };
-        Compress {
+        let mut comp = Compress {
             hash_tab: hash,
             chain,
+            base: 0,
             len,
+            dict_window,
             stop_at_match_len,
             max_search,
-            mask,
+            hash_bits,
+            max_hash_bits: hash_bits,
+            mask: (1 << hash_bits) - 1,
+            chain_mask: (1 << chain_bits) - 1,
             step,
-            level
+            level,
+            acceleration: 1,
+            dict_buffer: Vec::new(),
+            match_offsets: Vec::new(),
+            match_lens: Vec::new(),
+            indexes: Vec::new(),
+            costs: Vec::new(),
+        };
+        if level >= 9 {
+            comp.match_offsets.resize(len, 0);
+            comp.match_lens.resize(len, 0);
+            comp.indexes.resize(len, 0);
+            comp.costs.resize(len, 0);
         }
+        return comp;
     }
 
-    fn compress_block(&mut self, in_data: &Vec<u8>, in_len: usize, out_data: &mut Vec<u8>, o: usize) -> Result<usize, Error> {
-        if self.level >= 9 {
-            return self.compress_block_very_slow(in_data, in_len, out_data, o);
-        } else if self.level > 1 {
-            return self.compress_block_slow(in_data, in_len, out_data, o);
+    /// Set the acceleration of level 1, like "lz4 --fast=N". With values
+    /// above 1 (the default), more input is skipped when no match is found,
+    /// and a smaller hash table is used. This compresses less, and is
+    /// faster unless the data has many matches. Other levels are not
+    /// affected.
+    pub fn set_acceleration(&mut self, acceleration: usize) {
+        self.acceleration = max(acceleration, 1);
+        if self.level == 1 {
+            // a smaller table is faster, as it fits in the cache
+            let hash_bits = min(self.max_hash_bits, 12 - min(self.acceleration.ilog2(), 2));
+            if hash_bits != self.hash_bits {
+                self.hash_bits = hash_bits;
+                self.mask = (1 << hash_bits) - 1;
+                self.hash_tab.clear();
+                self.hash_tab.resize(1 << hash_bits, 0);
+                self.hash_tab.shrink_to_fit();
+            }
+        }
+    }
+
+    /// The memory used by the compressor, in bytes.
+    pub fn memory_usage(&self) -> usize {
+        return 4 * (self.hash_tab.capacity() + self.chain.capacity())
+            + self.dict_buffer.capacity()
+            + 4 * (self.match_offsets.capacity() + self.match_lens.capacity())
+            + std::mem::size_of::<usize>() * (self.indexes.capacity() + self.costs.capacity());
+    }
+
+    /// Clear the hash tables, without freeing memory. This is not needed
+    /// between blocks: the entries of previous blocks are ignored anyway.
+    pub fn reset(&mut self) {
+        self.hash_tab.fill(0);
+        self.chain.fill(0);
+        self.base = 0;
+    }
+
+    // Invalidate all entries of the hash and chain tables, by moving the
+    // base past the positions of the previous block. The tables are only
+    // cleared if the base would overflow.
+    fn start_block(&mut self) {
+        let max_len = self.len + self.dict_window;
+        let base = self.base + max_len + 1;
+        if base + max_len >= u32::MAX as usize {
+            self.hash_tab.fill(0);
+            self.chain.fill(0);
+            self.base = 1;
+        } else {
+            self.base = base;
+        }
+    }
+
+    // The table entry for a position.
+    fn entry(&self, pos: usize) -> u32 {
+        return (pos + self.base) as u32;
+    }
+
+    // The position of a table entry, or usize::MAX if the entry is from a
+    // previous block.
+    fn position(&self, entry: u32) -> usize {
+        let x = entry as usize;
+        if x < self.base {
+            return usize::MAX;
+        }
+        return x - self.base;
+    }
+
+    /// Add the last 64 KB of the history in_data[0..start] to the hash tables.
+    fn index_history(&mut self, in_data: &[u8], start: usize) {
+        let end = min(start, in_data.len().saturating_sub(8));
+        for p in start.saturating_sub(0xffff)..end {
+            if self.level == 1 {
+                self.hash_tab[hash5(in_data, p, self.hash_bits)] = self.entry(p);
+            } else if self.level < 9 {
+                let h = hash(in_data, p, self.mask);
+                self.chain[p & self.chain_mask] = self.hash_tab[h];
+                self.hash_tab[h] = self.entry(p);
+            }
+        }
+    }
+
+    /// Compress in_data into out_data, starting at position o. At least
+    /// compress_bound(in_data.len()) bytes must be available in out_data.
+    /// Returns the end position in out_data.
+    pub fn compress_block(&mut self, in_data: &[u8], out_data: &mut [u8], o: usize) -> Result<usize, Error> {
+        return self.compress_block_with_history(in_data, 0, out_data, o);
+    }
+
+    /// Compress in_data into out_data, starting at position o. Matches may
+    /// refer to the dictionary (only the last 64 KB are used, or less with
+    /// a memory limit). Returns the end position in out_data.
+    pub fn compress_block_with_dictionary(&mut self, dictionary: &[u8], in_data: &[u8], out_data: &mut [u8], o: usize) -> Result<usize, Error> {
+        if in_data.len() > self.len {
+            return Err(Error::InvalidInput("Temporary buffer too small"));
+        }
+        if self.dict_window == 0 {
+            return self.compress_block(in_data, out_data, o);
         }
-        if in_len > in_data.len() {
-            return error("Input buffer too small");
+        let dict = &dictionary[dictionary.len().saturating_sub(self.dict_window)..];
+        let mut buffer = std::mem::take(&mut self.dict_buffer);
+        buffer.clear();
+        buffer.reserve_exact(dict.len() + in_data.len());
+        buffer.extend_from_slice(dict);
+        buffer.extend_from_slice(in_data);
+        let result = self.compress_block_with_history(&buffer, dict.len(), out_data, o);
+        self.dict_buffer = buffer;
+        return result;
+    }
+
+    /// Compress in_data[start..] into out_data, starting at position o.
+    /// Matches may refer to the (at most 64 KB) history in_data[0..start],
+    /// as used for linked blocks. Returns the end position in out_data.
+    pub fn compress_block_with_history(&mut self, in_data: &[u8], start: usize, out_data: &mut [u8], o: usize) -> Result<usize, Error> {
+        let in_len = in_data.len();
+        if start > in_len {
+            return Err(Error::InvalidInput("Start after end of input"));
+        }
+        if o > out_data.len() || out_data.len() - o < compress_bound(in_len - start) {
+            return Err(Error::OutputTooSmall);
+        }
+        // with a dictionary, the input is longer
+        if in_len > self.len + self.dict_window {
+            return Err(Error::InvalidInput("Temporary buffer too small"));
         }
-        if in_len > self.len {
-            return error("Temporary buffer too small");
+        self.start_block();
+        if start > 0 {
+            self.index_history(in_data, start);
+        }
+        if self.level >= 9 {
+            return self.compress_block_very_slow(in_data, start, out_data, o);
+        } else if self.level > 1 {
+            return self.compress_block_slow(in_data, start, out_data, o);
         }
         let mut out_pos = o;
         let mut literal_len = 0;
-        let mut in_pos = 0;
+        let mut in_pos = start;
         let skip_trigger = 6;
-        let mut search_match = 1 << skip_trigger;
+        let mut search_match = self.acceleration << skip_trigger;
         loop {
             let mut run_len: usize;
             let mut candidate_pos: usize;
             if in_pos + 16 < in_len {
-                let h = hash5(in_data, in_pos);
-                candidate_pos = self.hash_tab[h] as usize;
-                self.hash_tab[h] = in_pos as u32;
+                let h = hash5(in_data, in_pos, self.hash_bits);
+                candidate_pos = self.position(self.hash_tab[h]);
+                self.hash_tab[h] = self.entry(in_pos);
+                // the position right after a match is always tried
+                let step = if literal_len == 0 { 1 } else { search_match >> skip_trigger };
                 if candidate_pos >= in_pos || candidate_pos + 0xffff < in_pos {
-                    let step = search_match >> skip_trigger;
                     literal_len += step;
                     in_pos += step;
                     search_match += 1;
                     continue;
                 } else {
-                    run_len = run_len_count(in_data, in_len, in_pos, candidate_pos);
+                    run_len = run_len_count(in_data, in_pos, candidate_pos);
                     if run_len < 4 {
-                        let step = search_match >> skip_trigger;
                         literal_len += step;
                         in_pos += step;
                         search_match += 1;
                         continue;
                     }
                     let p = in_pos + run_len - 2;
-                    let h = hash5(in_data, p);
-                    self.hash_tab[h] = p as u32;
+                    let h = hash5(in_data, p, self.hash_bits);
+                    self.hash_tab[h] = self.entry(p);
                 }
             } else {
                 // we reached the last few bytes in the block,
                 // which are always encoded as literals
-                literal_len += in_len - in_pos;
+                // (in_pos can be past the end after skipping)
+                literal_len = literal_len + in_len - in_pos;
                 in_pos = in_len;
                 run_len = 4;
                 candidate_pos = 0;
@@ -249,10 +720,7 @@ impl Compress {
                 out_pos += 1;
                 literal_len = 0xf;
             }
-            for i in 0..copy_len {
-                let x = in_data[in_pos - copy_len + i];
-                out_data[out_pos + i] = x;
-            }
+            out_data[out_pos..out_pos + copy_len].copy_from_slice(&in_data[in_pos - copy_len..in_pos]);
             out_pos += copy_len;
             if in_pos < in_len {
                 let offset = in_pos - candidate_pos;
@@ -269,7 +737,7 @@ impl Compress {
             run_len -= 4;
             if run_len >= 0xf {
                 while run_len - 0xf >= 0xff {
-                    out_data[out_pos] = 0xff as u8;
+                    out_data[out_pos] = 0xff;
                     out_pos += 1;
                     run_len -= 0xff;
                 }
@@ -281,37 +749,32 @@ impl Compress {
             out_data[tag_pos] = tag as u8;
             in_pos += skip_len;
             literal_len = 0;
-            search_match = 1 << skip_trigger;
+            search_match = self.acceleration << skip_trigger;
         }
         return Ok(out_pos);
     }
 
-    fn compress_block_slow(&mut self, in_data: &Vec<u8>, in_len: usize, out_data: &mut Vec<u8>, o: usize) -> Result<usize, Error> {
-        if in_len > in_data.len() {
-            return error("Input buffer too small");
-        }
-        if in_len > self.len {
-            return error("Temporary buffer too small");
-        }
+    fn compress_block_slow(&mut self, in_data: &[u8], start: usize, out_data: &mut [u8], o: usize) -> Result<usize, Error> {
+        let in_len = in_data.len();
         let mut out_pos = o;
         let mut literal_len = 0;
-        let mut in_pos = 0;
+        let mut in_pos = start;
         loop {
             let mut run_len: usize;
             let mut candidate_pos: usize;
             if in_pos + 16 < in_len {
                 let h = hash(in_data, in_pos, self.mask);
                 let first_candidate = self.hash_tab[h];
-                self.chain[in_pos] = first_candidate;
-                self.hash_tab[h] = in_pos as u32;
-                candidate_pos = first_candidate as usize;
+                self.chain[in_pos & self.chain_mask] = first_candidate;
+                self.hash_tab[h] = self.entry(in_pos);
+                candidate_pos = self.position(first_candidate);
                 let mut best_candidate: usize = 0;
                 let mut best_run_len: usize = 0;
                 for _ in 0..self.max_search {
                     if candidate_pos >= in_pos || candidate_pos + 0xffff < in_pos {
                         break;
                     } else {
-                        run_len = run_len_backwards(in_data, in_len, in_pos, candidate_pos, best_run_len);
+                        run_len = run_len_backwards(in_data, in_pos, candidate_pos, best_run_len);
                     }
                     if run_len > best_run_len {
                         best_run_len = run_len;
@@ -321,7 +784,7 @@ impl Compress {
                             break;
                         }
                     }
-                    candidate_pos = self.chain[candidate_pos] as usize;
+                    candidate_pos = self.position(self.chain[candidate_pos & self.chain_mask]);
                 }
                 candidate_pos = best_candidate;
                 run_len = best_run_len;
@@ -334,8 +797,8 @@ impl Compress {
                         let p = in_pos + i;
                         let h = hash(in_data, p, self.mask);
                         let c = self.hash_tab[h];
-                        self.chain[p] = c;
-                        self.hash_tab[h] = p as u32;
+                        self.chain[p & self.chain_mask] = c;
+                        self.hash_tab[h] = self.entry(p);
                     }
                 }
             } else {
@@ -380,7 +843,7 @@ impl Compress {
             run_len -= 4;
             if run_len >= 0xf {
                 while run_len - 0xf >= 0xff {
-                    out_data[out_pos] = 0xff as u8;
+                    out_data[out_pos] = 0xff;
                     out_pos += 1;
                     run_len -= 0xff;
                 }
@@ -397,29 +860,31 @@ impl Compress {
         return Ok(out_pos);
     }
 
-    fn compress_block_very_slow(&mut self, in_data: &Vec<u8>, in_len: usize, out_data: &mut Vec<u8>, o: usize) -> Result<usize, Error> {
-        if in_len > in_data.len() {
-            return error("Input buffer too small");
-        }
-        if in_len > self.len {
-            return error("Temporary buffer too small");
-        }
-
-        let mut match_offsets: Vec<u32> = Vec::new();
+    fn compress_block_very_slow(&mut self, in_data: &[u8], start: usize, out_data: &mut [u8], o: usize) -> Result<usize, Error> {
+        let in_len = in_data.len();
+        // the buffers are reused, and only grow if the input is larger
+        let mut match_offsets = std::mem::take(&mut self.match_offsets);
+        match_offsets.clear();
+        match_offsets.reserve_exact(in_len);
         match_offsets.resize(in_len, 0);
-        let mut match_lens: Vec<u32> = Vec::new();
+        let mut match_lens = std::mem::take(&mut self.match_lens);
+        match_lens.clear();
+        match_lens.reserve_exact(in_len);
         match_lens.resize(in_len, 0);
-
-        let mut indexes: Vec<usize> = Vec::new();
+        let mut indexes = std::mem::take(&mut self.indexes);
+        indexes.clear();
+        indexes.reserve_exact(in_len);
         indexes.resize(in_len, 0);
-        let mut block_start = 0;
+        // the history (if any) is only used for match candidates
+        let history_start = start.saturating_sub(0x10000);
+        let mut block_start = history_start;
         while block_start < in_len {
             let block_end = min(block_start + 0x20000, indexes.len());
             for i in block_start..block_end {
                 indexes[i] = i;
             }
-            indexes[block_start..block_end].sort_by(|a, b| { return compare_at(&in_data, a, b) });
-            let update_start = if block_start == 0 { 0 } else { block_start + 0x10000 };
+            indexes[block_start..block_end].sort_by(|a, b| { return compare_at(in_data, a, b) });
+            let update_start = if block_start == history_start { start } else { block_start + 0x10000 };
             for x in block_start..block_end {
                 let a = indexes[x];
                 if a < update_start || a >= block_end {
@@ -429,7 +894,7 @@ impl Compress {
                     if x >= block_start + i {
                         let b1 = indexes[x - i];
                         if a > b1 && a - b1 < 0xffff {
-                            let run_len = run_len_count(in_data, in_len, a, b1);
+                            let run_len = run_len_count(in_data, a, b1);
                             if run_len >= 4 && run_len > match_lens[a] as usize {
                                 match_lens[a] = run_len as u32;
                                 match_offsets[a] = (a - b1) as u32;
@@ -442,7 +907,7 @@ impl Compress {
                     if x + i < block_end {
                         let b2 = indexes[x + i];
                         if a > b2 && a - b2 < 0xffff {
-                            let run_len = run_len_count(in_data, in_len, a, b2);
+                            let run_len = run_len_count(in_data, a, b2);
                             if run_len >= 4 && run_len > match_lens[a] as usize {
                                 match_lens[a] = run_len as u32;
                                 match_offsets[a] = (a - b2) as u32;
@@ -454,17 +919,21 @@ impl Compress {
             }
             block_start += 0x10000;
         }
-        let mut in_pos = in_len - 12;
+        // the last 12 bytes (or the whole block if it is small) are literals
+        let tail_start = max(start, in_len.saturating_sub(12));
+        let mut in_pos = tail_start;
         // minimum cost (compressed size) from each position (0 if unknown) in bytes
-        let mut costs: Vec<usize> = Vec::new();
+        let mut costs = std::mem::take(&mut self.costs);
+        costs.clear();
+        costs.reserve_exact(in_len);
         costs.resize(in_len, 0);
         let mut literal_count = 0;
-        for i in in_len - 12 .. in_len {
+        for i in tail_start..in_len {
             costs[i] = literal_count + 1;
             literal_count += 1;
         }
         let mut best_len: usize;
-        while in_pos > 0 {
+        while in_pos > start {
             // assume literal
             literal_count += 1;
             best_len = 1;
@@ -505,8 +974,8 @@ impl Compress {
             in_pos -= 1;
         }
         let mut out_pos = o;
-        let mut literal_len = min(4, in_len);
-        let mut in_pos = literal_len;
+        let mut literal_len = min(4, in_len - start);
+        let mut in_pos = start + literal_len;
         loop {
             let mut run_len: usize;
             if in_pos + 16 < in_len {
@@ -555,7 +1024,7 @@ impl Compress {
             run_len -= 4;
             if run_len >= 0xf {
                 while run_len - 0xf >= 0xff {
-                    out_data[out_pos] = 0xff as u8;
+                    out_data[out_pos] = 0xff;
                     out_pos += 1;
                     run_len -= 0xff;
                 }
@@ -568,13 +1037,23 @@ impl Compress {
             in_pos += skip_len;
             literal_len = 0;
         }
+        self.match_offsets = match_offsets;
+        self.match_lens = match_lens;
+        self.indexes = indexes;
+        self.costs = costs;
         return Ok(out_pos);
     }
 }
 
 #[cfg(test)]
 mod tests {
+    use crate::decompress::decompress;
     use crate::decompress::decompress_block;
+    use crate::decompress::decompress_block_with_dictionary;
+    use crate::decompress::decompress_block_into;
+    use crate::decompress::decompress_to_vec;
+    use crate::decompress::tests::random_bytes;
+    use crate::decompress::tests::test_data;
     use super::*;
 
     #[test]
@@ -588,10 +1067,10 @@ mod tests {
         out_block.resize(2 * 1024, 0);
         for level in 1..10 {
             let mut comp = Compress::new(1024, level);
-            let end = comp.compress_block(&block, block.len(), &mut out_block, 0).unwrap();
+            let end = comp.compress_block(&block, &mut out_block, 0).unwrap();
             let mut test_block: Vec<u8> = Vec::new();
             test_block.resize(1024, 0);
-            let test_end = decompress_block(&out_block, end, &mut test_block, 0).unwrap();
+            let test_end = decompress_block(&out_block[0..end], &mut test_block, 0).unwrap();
             assert_eq!(test_end, 1024, "level {level}");
             for i in 0..1024 {
                 assert_eq!(test_block[i], block[i], "at {i}");
@@ -599,4 +1078,312 @@ mod tests {
         }
     }
 
-}
\ No newline at end of file
+    #[test]
+    fn compress_decompress_frame() {
+        let data = test_data(100000);
+        for level in 1..10 {
+            let mut compressed: Vec<u8> = Vec::new();
+            let size = compress(&data[..], &mut compressed, level).unwrap();
+            assert_eq!(size, data.len());
+            let mut test: Vec<u8> = Vec::new();
+            let test_size = decompress(&compressed[..], &mut test).unwrap();
+            assert_eq!(test_size, data.len(), "level {level}");
+            assert_eq!(test, data, "level {level}");
+        }
+    }
+
+    #[test]
+    fn compress_without_content_checksum() {
+        let data = "Hello world, hello world, hello world!".as_bytes().to_vec();
+        let mut with_checksum: Vec<u8> = Vec::new();
+        compress(&data[..], &mut with_checksum, 1).unwrap();
+        let mut options = CompressOptions::new(1);
+        options.content_checksum = false;
+        let mut compressed: Vec<u8> = Vec::new();
+        compress_with_options(&data[..], &mut compressed, &options).unwrap();
+        assert_eq!(with_checksum[4] & (1 << 2), 1 << 2);
+        assert_eq!(compressed[4] & (1 << 2), 0);
+        assert_eq!(compressed.len() + 4, with_checksum.len());
+    }
+
+    #[test]
+    fn compress_with_content_size() {
+        let data = "Hello world, hello world, hello world!".as_bytes().to_vec();
+        let mut options = CompressOptions::new(1);
+        options.content_size = Some(data.len() as u64);
+        let mut compressed: Vec<u8> = Vec::new();
+        compress_with_options(&data[..], &mut compressed, &options).unwrap();
+        assert_eq!(compressed[4] & (1 << 3), 1 << 3);
+        assert_eq!(read_u64_le(&compressed, 6), data.len() as u64);
+        options.content_size = Some(data.len() as u64 + 1);
+        let result = compress_with_options(&data[..], &mut Vec::new(), &options);
+        assert!(matches!(result.unwrap_err(), Error::ContentSize { expected: 39, actual: 38 }));
+    }
+
+    #[test]
+    fn compress_block_with_history() {
+        let mut block: Vec<u8> = Vec::new();
+        for i in 0..2048 {
+            block.push((i * 7 + i / 100) as u8);
+        }
+        // the second half repeats the first half
+        for i in 0..1024 {
+            block[1024 + i] = block[i];
+        }
+        let mut out_block: Vec<u8> = Vec::new();
+        out_block.resize(4 * 1024, 0);
+        for level in 1..10 {
+            let mut comp = Compress::new(2048, level);
+            let end = comp.compress_block_with_history(&block, 1024, &mut out_block, 0).unwrap();
+            assert!(end < 100, "level {level} size {end}");
+            let mut test_block: Vec<u8> = Vec::new();
+            test_block.resize(2048, 0);
+            test_block[0..1024].copy_from_slice(&block[0..1024]);
+            let test_end = decompress_block(&out_block[0..end], &mut test_block, 1024).unwrap();
+            assert_eq!(test_end, 2048, "level {level}");
+            assert_eq!(test_block, block, "level {level}");
+        }
+    }
+
+    #[test]
+    fn compress_linked_blocks() {
+        let data = test_data(100000);
+        let mut options = CompressOptions::new(2);
+        options.block_max_size = 4;
+        let mut independent: Vec<u8> = Vec::new();
+        compress_with_options(&data[..], &mut independent, &options).unwrap();
+        options.linked_blocks = true;
+        let mut compressed: Vec<u8> = Vec::new();
+        compress_with_options(&data[..], &mut compressed, &options).unwrap();
+        assert_eq!(compressed[4] & (1 << 5), 0);
+        assert!(compressed.len() < independent.len());
+        let mut test: Vec<u8> = Vec::new();
+        decompress(&compressed[..], &mut test).unwrap();
+        assert_eq!(test, data);
+    }
+
+    #[test]
+    fn compress_block_max_size() {
+        let data = test_data(300000);
+        for block_max_size in 4..8 {
+            let mut options = CompressOptions::new(1);
+            options.block_max_size = block_max_size;
+            let mut compressed: Vec<u8> = Vec::new();
+            compress_with_options(&data[..], &mut compressed, &options).unwrap();
+            assert_eq!(compressed[5], block_max_size << 4);
+            let mut test: Vec<u8> = Vec::new();
+            decompress(&compressed[..], &mut test).unwrap();
+            assert_eq!(test, data, "block max size {block_max_size}");
+        }
+        let mut options = CompressOptions::new(1);
+        options.block_max_size = 3;
+        let result = compress_with_options(&data[..], &mut Vec::new(), &options);
+        assert_eq!(result.unwrap_err().to_string(), "Unsupported block max size 3");
+    }
+
+    #[test]
+    fn compress_to_vec_and_block_into() {
+        let data = test_data(300000);
+        for len in [0, 1, 20, 1000, 300000] {
+            let data = &data[0..len];
+            for legacy in [false, true] {
+                let mut options = CompressOptions::new(1);
+                options.block_max_size = 5;
+                options.legacy_format = legacy;
+                let compressed = compress_to_vec(data, &options).unwrap();
+                // the same as when compressing a stream of known size
+                options.content_size = Some(len as u64);
+                let mut expected: Vec<u8> = Vec::new();
+                compress_with_options(data, &mut expected, &options).unwrap();
+                assert_eq!(compressed, expected, "len {len}");
+                let test = decompress_to_vec(&compressed).unwrap();
+                assert_eq!(test, data);
+                if !legacy {
+                    assert_eq!(test.capacity(), len);
+                }
+            }
+            let mut block: Vec<u8> = Vec::new();
+            block.resize(compress_bound(len), 0);
+            let end = compress_block_into(data, &mut block).unwrap();
+            let mut test: Vec<u8> = Vec::new();
+            test.resize(len, 0);
+            assert_eq!(decompress_block_into(&block[0..end], &mut test).unwrap(), len);
+            assert_eq!(test, data);
+            if len > 0 {
+                let result = decompress_block_into(&block[0..end], &mut test[0..len - 1]);
+                assert!(matches!(result, Err(Error::OutputTooSmall)));
+            }
+        }
+    }
+
+    #[test]
+    fn compress_block_stack_arrays() {
+        let mut block: [u8; 64] = [0; 64];
+        for i in 0..64 {
+            block[i] = (i % 5) as u8;
+        }
+        let mut out_block: [u8; 96] = [0; 96];
+        let mut comp = Compress::new(64, 1);
+        let end = comp.compress_block(&block, &mut out_block, 0).unwrap();
+        let mut test_block: [u8; 64] = [0; 64];
+        assert_eq!(decompress_block(&out_block[0..end], &mut test_block, 0).unwrap(), 64);
+        assert_eq!(test_block, block);
+        let result = comp.compress_block(&block, &mut out_block[0..compress_bound(64) - 1], 0);
+        assert!(matches!(result, Err(Error::OutputTooSmall)));
+    }
+
+    #[test]
+    fn compress_with_acceleration() {
+        let mut data: Vec<u8> = Vec::new();
+        for i in 0..200000 {
+            data.extend_from_slice(format!("{} ", i * 7919 % 10007).as_bytes());
+        }
+        let mut sizes: Vec<usize> = Vec::new();
+        for acceleration in [1, 2, 8, 32] {
+            let mut options = CompressOptions::new(1);
+            options.acceleration = acceleration;
+            let compressed = compress_to_vec(&data, &options).unwrap();
+            assert_eq!(decompress_to_vec(&compressed).unwrap(), data);
+            sizes.push(compressed.len());
+        }
+        assert_eq!(sizes[0], compress_to_vec(&data, &CompressOptions::new(1)).unwrap().len());
+        // faster, but larger
+        assert!(sizes[3] > sizes[0]);
+        // the hash table is smaller
+        let mut comp = Compress::new(data.len(), 1);
+        let memory = comp.memory_usage();
+        comp.set_acceleration(32);
+        assert!(comp.memory_usage() < memory);
+        comp.set_acceleration(1);
+        assert_eq!(comp.memory_usage(), memory);
+    }
+
+    #[test]
+    fn compress_block_with_memory_limit() {
+        let mut data: Vec<u8> = Vec::new();
+        for i in 0..200000 {
+            data.push((i % 251 + i / 5000) as u8);
+        }
+        let mut out_block: Vec<u8> = Vec::new();
+        out_block.resize(compress_bound(data.len()), 0);
+        for level in 1..10 {
+            assert!(Compress::new(data.len(), level).memory_usage() > 8 * 1024);
+            let mut comp = Compress::with_memory_limit(data.len(), level, 8 * 1024);
+            let memory = comp.memory_usage();
+            assert!(memory <= 8 * 1024);
+            // many small payloads, without allocating more memory
+            for chunk in data.chunks(3000) {
+                let end = comp.compress_block(chunk, &mut out_block, 0).unwrap();
+                let mut test: Vec<u8> = Vec::new();
+                test.resize(chunk.len(), 0);
+                assert_eq!(decompress_block(&out_block[0..end], &mut test, 0).unwrap(), chunk.len());
+                assert_eq!(test, chunk);
+            }
+            let end = comp.compress_block(&data, &mut out_block, 0).unwrap();
+            let expected = out_block[0..end].to_vec();
+            comp.reset();
+            let end = comp.compress_block(&data, &mut out_block, 0).unwrap();
+            assert_eq!(out_block[0..end], expected[..]);
+            assert_eq!(comp.memory_usage(), memory);
+        }
+        let comp = Compress::with_memory_limit(1000, 9, 24000);
+        assert_eq!(comp.memory_usage(), 24000);
+    }
+
+    #[test]
+    fn compress_block_with_memory_limit_same_output() {
+        // if the tables fit, they are not made smaller, even if the
+        // dictionary does not fit
+        let comp = Compress::with_memory_limit(4 << 20, 5, 2 << 20);
+        assert_eq!(comp.memory_usage(), Compress::new(4 << 20, 5).memory_usage());
+        let data = test_data(300000);
+        let mut out_block: Vec<u8> = Vec::new();
+        out_block.resize(compress_bound(data.len()), 0);
+        for level in 1..10 {
+            let mut comp = Compress::new(data.len(), level);
+            let memory = comp.memory_usage();
+            let end = comp.compress_block(&data, &mut out_block, 0).unwrap();
+            let expected = out_block[0..end].to_vec();
+            let mut comp = Compress::with_memory_limit(data.len(), level, memory);
+            assert_eq!(comp.memory_usage(), memory);
+            let end = comp.compress_block(&data, &mut out_block, 0).unwrap();
+            assert_eq!(out_block[0..end], expected[..]);
+        }
+    }
+
+    #[test]
+    fn compress_block_with_dictionary_and_memory_limit() {
+        let mut dictionary: Vec<u8> = Vec::new();
+        random_bytes(&mut dictionary, 64 * 1024, 1);
+        let mut data = dictionary[60000..64000].to_vec();
+        data[100] ^= 1;
+        let mut out_block: Vec<u8> = Vec::new();
+        out_block.resize(compress_bound(data.len()), 0);
+        for max_memory in [20_000, 100_000, 2_000_000] {
+            for level in 1..10 {
+                let mut comp = Compress::with_memory_limit(data.len(), level, max_memory);
+                let end = comp.compress_block_with_dictionary(&dictionary, &data, &mut out_block, 0).unwrap();
+                assert!(comp.memory_usage() <= max_memory);
+                let mut test: Vec<u8> = Vec::new();
+                test.resize(data.len(), 0);
+                decompress_block_with_dictionary(&out_block[0..end], &dictionary, &mut test, 0).unwrap();
+                assert_eq!(test, data);
+                if max_memory == 2_000_000 {
+                    // the whole dictionary is used
+                    assert!(end < 100);
+                }
+                let result = comp.compress_block_with_dictionary(&dictionary, &dictionary[0..5000], &mut out_block, 0);
+                assert!(matches!(result, Err(Error::InvalidInput(_))));
+            }
+        }
+    }
+
+    #[test]
+    fn compress_block_independent_of_previous_blocks() {
+        let mut data: Vec<u8> = Vec::new();
+        for i in 0..30000 {
+            data.push((i % 97 + i / 1000) as u8);
+        }
+        let mut other: Vec<u8> = Vec::new();
+        for i in 0..30000 {
+            other.push((i % 89 + i / 700) as u8);
+        }
+        let mut out_block: Vec<u8> = Vec::new();
+        out_block.resize(compress_bound(data.len()), 0);
+        for level in 1..10 {
+            let mut comp = Compress::new(data.len(), level);
+            let end = comp.compress_block(&data, &mut out_block, 0).unwrap();
+            let expected = out_block[0..end].to_vec();
+            let end = comp.compress_block_with_history(&data, 20000, &mut out_block, 0).unwrap();
+            let expected_history = out_block[0..end].to_vec();
+            // after other blocks, with different and the same content
+            for previous in [&other, &data, &other[0..1000].to_vec()] {
+                comp.compress_block(previous, &mut out_block, 0).unwrap();
+                let end = comp.compress_block(&data, &mut out_block, 0).unwrap();
+                assert_eq!(out_block[0..end], expected[..]);
+                comp.compress_block(previous, &mut out_block, 0).unwrap();
+                let end = comp.compress_block_with_history(&data, 20000, &mut out_block, 0).unwrap();
+                assert_eq!(out_block[0..end], expected_history[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn compress_block_with_dictionary() {
+        let dictionary = "{\"id\": 0, \"name\": \"\", \"email\": \"@example.com\", \"active\": true}".as_bytes().to_vec();
+        let block = "{\"id\": 42, \"name\": \"test\", \"email\": \"test@example.com\", \"active\": false}".as_bytes().to_vec();
+        let mut out_block: Vec<u8> = Vec::new();
+        out_block.resize(1024, 0);
+        for level in 1..10 {
+            let mut comp = Compress::new(block.len(), level);
+            let plain_end = comp.compress_block(&block, &mut out_block, 0).unwrap();
+            let end = comp.compress_block_with_dictionary(&dictionary, &block, &mut out_block, 0).unwrap();
+            assert!(end < plain_end, "level {level}");
+            let mut test_block: Vec<u8> = Vec::new();
+            test_block.resize(block.len(), 0);
+            let test_end = decompress_block_with_dictionary(&out_block[0..end], &dictionary, &mut test_block, 0).unwrap();
+            assert_eq!(test_end, block.len(), "level {level}");
+            assert_eq!(test_block, block, "level {level}");
+        }
+    }
+}
@@ -1,12 +1,15 @@
+use std::cmp::min;
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::io::BufWriter;
 use std::fs::File;
-use std::io::Error;
+use std::io;
 
-use crate::xxhash32::read_vec_u32_le;
+use crate::xxhash32::read_u32_le;
+use crate::xxhash32::read_u64_le;
 use crate::xxhash32::XXHash32;
-use crate::xxhash32::error;
+use crate::error::Error;
+use crate::xxhash32::read_fully;
 
 pub fn decompress_stream() -> Result<usize, Error> {
     return decompress(std::io::stdin(), std::io::stdout());
@@ -18,101 +21,530 @@ pub fn decompress_file(input_file_name: &str, output_file_name: &str) -> Result<
     return decompress(in_file, out_file);
 }
 
+/// Options for decompressing a frame.
+pub struct DecompressOptions {
+    /// The dictionary that was used to compress the frame (only the last
+    /// 64 KB are used).
+    pub dictionary: Option<Vec<u8>>,
+    /// The expected dictionary id. If set, and the frame header contains a
+    /// different dictionary id, decompression fails.
+    pub dictionary_id: Option<u32>,
+    /// The maximum number of decompressed bytes (of all frames). If more
+    /// data would be decompressed, decompression fails.
+    pub max_output_size: Option<u64>,
+    /// The maximum ratio between the number of decompressed bytes and
+    /// the number of compressed bytes read so far.
+    pub max_ratio: Option<u64>,
+}
+
+impl DecompressOptions {
+    pub fn new() -> DecompressOptions {
+        DecompressOptions {
+            dictionary: None,
+            dictionary_id: None,
+            max_output_size: None,
+            max_ratio: None,
+        }
+    }
+
+    // Check the limits, after a block was decompressed.
+    pub(crate) fn check_output_size(&self, input_size: usize, output_size: usize) -> Result<(), Error> {
+        let size = output_size as u64;
+        if let Some(limit) = self.max_output_size {
+            if size > limit {
+                return Err(Error::OutputLimit { size, limit });
+            }
+        }
+        if let Some(ratio) = self.max_ratio {
+            let limit = (input_size as u64).saturating_mul(ratio);
+            if size > limit {
+                return Err(Error::OutputLimit { size, limit });
+            }
+        }
+        return Ok(());
+    }
+}
+
+impl Default for DecompressOptions {
+    fn default() -> DecompressOptions {
+        return DecompressOptions::new();
+    }
+}
+
 pub fn decompress<R: Read, W: Write>(read: R, write: W) -> Result<usize, Error> {
+    return decompress_with_options(read, write, &DecompressOptions::new());
+}
+
+pub fn decompress_with_options<R: Read, W: Write>(read: R, write: W, options: &DecompressOptions) -> Result<usize, Error> {
+    return decompress_with_callback(read, write, options, None);
+}
+
+/// Decompress, and call skippable_frame with the magic number and the data
+/// of each skippable frame.
+pub fn decompress_with_skippable_frames<R: Read, W: Write, F: FnMut(u32, &[u8])>(read: R, write: W, options: &DecompressOptions, mut skippable_frame: F) -> Result<usize, Error> {
+    return decompress_with_callback(read, write, options, Some(&mut skippable_frame));
+}
+
+// Called with the magic number and the data of each skippable frame.
+type SkippableFrameFn<'a> = dyn FnMut(u32, &[u8]) + 'a;
+
+// Decompress, and return the decompressed size.
+fn decompress_with_callback<R: Read, W: Write>(read: R, write: W, options: &DecompressOptions, skippable_frame: Option<&mut SkippableFrameFn>) -> Result<usize, Error> {
+    let frames = decompress_frames(read, write, options, skippable_frame)?;
+    let mut output_size = 0;
+    for frame in frames {
+        output_size += frame.decompressed_size;
+    }
+    return Ok(output_size);
+}
+
+/// Decompress, and return information about each frame.
+pub fn decompress_with_frame_info<R: Read, W: Write>(read: R, write: W, options: &DecompressOptions) -> Result<Vec<FrameInfo>, Error> {
+    return decompress_frames(read, write, options, None);
+}
+
+/// Information about a frame.
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameInfo {
+    /// The magic number.
+    pub magic: u32,
+    /// The size of the frame, including the header.
+    pub compressed_size: usize,
+    /// The number of decompressed bytes (0 for skippable frames).
+    pub decompressed_size: usize,
+    /// The number of data blocks.
+    pub block_count: usize,
+}
+
+/// Decompress all frames of the data into a vector.
+pub fn decompress_to_vec(data: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut output: Vec<u8> = Vec::new();
+    // reserve the content size of the first frame, if known; a block can
+    // not expand to more than 255 times its size
+    if data.len() >= 14 && read_u32_le(data, 0) == 0x184D2204 && (data[4] >> 3) & 1 == 1 {
+        let content_size = read_u64_le(data, 6);
+        output.reserve_exact(min(content_size, 255 * data.len() as u64) as usize);
+    }
+    read_frames(&mut &data[..], &mut output, &DecompressOptions::new(), None)?;
+    return Ok(output);
+}
+
+// Decompress all frames until the end of the input. The data of skippable
+// frames is only read into memory if there is a callback.
+fn decompress_frames<R: Read, W: Write>(read: R, write: W, options: &DecompressOptions, skippable_frame: Option<&mut SkippableFrameFn>) -> Result<Vec<FrameInfo>, Error> {
     let mut reader = BufReader::new(read);
-    let mut header: Vec<u8> = Vec::new();
-    header.resize(7, 0);
-    reader.read_exact(&mut header)?;
-    let magic = read_vec_u32_le(&header, 0);
-    if magic != 0x184D2204 {
-        return error(format!("Incorrect magic {magic}").as_str());
-    }
-    let flags = header[4];
-    let version = flags >> 6;
-    if version != 1 {
-        return error(format!("Unsupported version {version}").as_str());
-    }
-    let block_independance_flag = ((flags >> 5) & 1) == 1;
-    if !block_independance_flag {
-        return error("Unsupported block dependence");
-    }
-    let block_checksum_flag = ((flags >> 4) & 1) == 1;
-    if block_checksum_flag {
-        return error("Unsupported block checksum flag");
-    }
-    let content_size_flag = ((flags >> 3) & 1) == 1;
-    if content_size_flag {
-        return error("Unsupported content size flag");
-    }
-    let content_checksum_flag = ((flags >> 2) & 1) == 1;
-    if content_checksum_flag {
-        // TODO currently, the content checksum flag is ignored
-        // return error("Unsupported content checksum flag");
-    }
-    if (flags >> 1) & 1 != 0 {
-        return error("Unsupported reserved");
-    }
-    if (flags & 1) == 1 {
-        return error("Unsupported dict flag");
-    }
-    let bd = header[5];
-    let block_max_size = (bd >> 4) & 0x7;
-    if block_max_size < 4 || block_max_size > 7 {
-        return error(format!("Unsupported block max size {block_max_size}").as_str());
-    }
-    let header_checksum = header[6];
-    let mut hash = XXHash32::new(0);
-    let xxhash = (hash.update(&header, 4, 2)? >> 8) & 0xff;
-    if xxhash as u8 != header_checksum {
-        return error("Header checksum mismatch");
-    }
-    let mut block: Vec<u8> = Vec::new();
-    block.resize(4 * 1024 * 1024, 0);
-    let mut out_block: Vec<u8> = Vec::new();
-    out_block.resize(4 * 1024 * 1024, 0);
     let mut writer = BufWriter::new(write);
-    let mut output_file_size = 0;
+    let frames = read_frames(&mut reader, &mut writer, options, skippable_frame)?;
+    drop(reader);
+    writer.flush()?;
+    drop(writer);
+    return Ok(frames);
+}
+
+fn read_frames<R: Read, W: Write>(reader: &mut R, writer: &mut W, options: &DecompressOptions, mut skippable_frame: Option<&mut SkippableFrameFn>) -> Result<Vec<FrameInfo>, Error> {
+    let mut frames: Vec<FrameInfo> = Vec::new();
+    let mut header: Vec<u8> = Vec::new();
+    header.resize(4, 0);
+    // the magic number of the next frame, if already read
+    let mut next_magic: Option<u32> = None;
+    // the position of the current frame in the stream
+    let mut frame_pos = 0;
+    let mut output_size = 0;
     loop {
-        reader.read_exact(&mut header[0..4])?;
-        let mut block_size = read_vec_u32_le(&header, 0) as usize;
-        if block_size == 0 {
-            break;
+        let magic;
+        if let Some(m) = next_magic.take() {
+            magic = m;
+        } else {
+            let read = read_fully(&mut *reader, &mut header[0..4])?;
+            if read == 0 && !frames.is_empty() {
+                break;
+            }
+            if read < 4 {
+                return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated frame header")));
+            }
+            magic = read_u32_le(&header, 0);
+        }
+        if is_skippable_magic(magic) {
+            reader.read_exact(&mut header[0..4])?;
+            let size = read_u32_le(&header, 0) as u64;
+            let mut data: Vec<u8> = Vec::new();
+            let read = match &mut skippable_frame {
+                Some(_) => (&mut *reader).take(size).read_to_end(&mut data)? as u64,
+                None => io::copy(&mut (&mut *reader).take(size), &mut io::sink())?,
+            };
+            if read != size {
+                return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated skippable frame")));
+            }
+            if let Some(callback) = &mut skippable_frame {
+                callback(magic, &data);
+            }
+            let read = read as usize;
+            frame_pos += 8 + read;
+            frames.push(FrameInfo {
+                magic,
+                compressed_size: 8 + read,
+                decompressed_size: 0,
+                block_count: 0,
+            });
+        } else {
+            let mut frame = FrameState::new(&mut *reader, magic, options, frame_pos)?;
+            while frame.next_block(&mut *reader)? {
+                output_size += frame.output().len();
+                options.check_output_size(frame.stream_pos(), output_size)?;
+                writer.write_all(frame.output())?;
+            }
+            next_magic = frame.next_magic;
+            let info = frame.info();
+            frame_pos += info.compressed_size;
+            frames.push(info);
+        }
+    }
+    return Ok(frames);
+}
+
+// Convert an error of the block at the given position in the stream.
+fn block_error(e: Error, block_pos: usize) -> Error {
+    return match e {
+        Error::CorruptBlock { offset, message } => Error::CorruptBlock { offset: block_pos + offset, message },
+        // the block is larger than the block max size
+        Error::OutputTooSmall => Error::CorruptBlock { offset: block_pos, message: "Block too large" },
+        e => e,
+    };
+}
+
+// The state of decompressing one frame, block by block.
+pub(crate) struct FrameState {
+    magic: u32,
+    // legacy frames have no end mark: they end at the end of the input, or
+    // when the next frame starts
+    legacy: bool,
+    block_checksum_flag: bool,
+    content_checksum_flag: bool,
+    content_size: Option<u64>,
+    // with linked blocks, the last 64 KB of output are kept
+    // at the start of out_block, so that matches can refer to them;
+    // the same is done for the dictionary
+    linked: bool,
+    history_size: usize,
+    history_len: usize,
+    block_max_len: usize,
+    compressed_max_len: usize,
+    // the buffers grow as needed, so that small frames only need little memory
+    block: Vec<u8>,
+    out_block: Vec<u8>,
+    // the output of the current block is in block (for stored blocks),
+    // or in out_block after the history
+    stored: bool,
+    out_len: usize,
+    content_hash: XXHash32,
+    decompressed_size: usize,
+    // position in the compressed stream and index of the current block,
+    // for error messages
+    frame_pos: usize,
+    in_pos: usize,
+    block_index: usize,
+    done: bool,
+    // the magic number of the next frame, if it was read
+    pub(crate) next_magic: Option<u32>,
+}
+
+impl FrameState {
+    // Read the frame header, after the magic number was read.
+    pub(crate) fn new<R: Read>(reader: &mut R, magic: u32, options: &DecompressOptions, frame_pos: usize) -> Result<FrameState, Error> {
+        if magic == 0x184C2102 {
+            let block_max_len = 8 * 1024 * 1024;
+            return Ok(FrameState::with_buffers(magic, block_max_len, block_max_len + block_max_len / 255 + 16, 0, frame_pos, 4));
         }
-        let uncompressed = ((block_size >> 31) & 1) == 1;
-        block_size &= 0x7fffffff;
-        if block_size > 4 * 1024 * 1024 {
-            return error(format!("Unsupported block size {block_size}").as_str());
+        // magic, flags, block descriptor, content size, dictionary id, header checksum
+        let mut header: Vec<u8> = Vec::new();
+        header.resize(19, 0);
+        if magic != 0x184D2204 {
+            return Err(Error::BadMagic(magic));
         }
-        reader.read_exact(&mut block[0..block_size])?;
-        if uncompressed {
-            writer.write_all(&block)?;
-            output_file_size += block_size;
+        let mut header_len = 6;
+        reader.read_exact(&mut header[4..header_len])?;
+        let flags = header[4];
+        let version = flags >> 6;
+        if version != 1 {
+            return Err(Error::Unsupported(format!("version {version}")));
+        }
+        let block_independance_flag = ((flags >> 5) & 1) == 1;
+        let block_checksum_flag = ((flags >> 4) & 1) == 1;
+        let content_size_flag = ((flags >> 3) & 1) == 1;
+        let content_checksum_flag = ((flags >> 2) & 1) == 1;
+        if (flags >> 1) & 1 != 0 {
+            return Err(Error::Unsupported("reserved flag".to_string()));
+        }
+        let dict_id_flag = (flags & 1) == 1;
+        let bd = header[5];
+        let block_max_size = (bd >> 4) & 0x7;
+        if block_max_size < 4 || block_max_size > 7 {
+            return Err(Error::Unsupported(format!("block max size {block_max_size}")));
+        }
+        let mut content_size = None;
+        if content_size_flag {
+            reader.read_exact(&mut header[header_len..header_len + 8])?;
+            content_size = Some(read_u64_le(&header, header_len));
+            header_len += 8;
+        }
+        if dict_id_flag {
+            reader.read_exact(&mut header[header_len..header_len + 4])?;
+            let dict_id = read_u32_le(&header, header_len);
+            header_len += 4;
+            if options.dictionary.is_none() {
+                return Err(Error::MissingDictionary(dict_id));
+            }
+            if let Some(expected) = options.dictionary_id {
+                if expected != dict_id {
+                    return Err(Error::DictionaryId { expected, actual: dict_id });
+                }
+            }
+        }
+        reader.read_exact(&mut header[header_len..header_len + 1])?;
+        let header_checksum = header[header_len];
+        let mut hash = XXHash32::new(0);
+        let xxhash = (hash.update(&header[4..header_len]) >> 8) & 0xff;
+        if xxhash as u8 != header_checksum {
+            return Err(Error::HeaderChecksum);
+        }
+        let block_max_len = 1 << (8 + 2 * block_max_size);
+        let linked = !block_independance_flag;
+        let history_size = if linked || options.dictionary.is_some() { 64 * 1024 } else { 0 };
+        let mut frame = FrameState::with_buffers(magic, block_max_len, block_max_len, history_size, frame_pos, header_len + 1);
+        frame.legacy = false;
+        frame.block_checksum_flag = block_checksum_flag;
+        frame.content_checksum_flag = content_checksum_flag;
+        frame.content_size = content_size;
+        frame.linked = linked;
+        if let Some(dictionary) = &options.dictionary {
+            let dict = &dictionary[dictionary.len().saturating_sub(history_size)..];
+            frame.out_block.resize(dict.len(), 0);
+            frame.out_block.copy_from_slice(dict);
+            frame.history_len = dict.len();
+        }
+        return Ok(frame);
+    }
+
+    fn with_buffers(magic: u32, block_max_len: usize, compressed_max_len: usize, history_size: usize, frame_pos: usize, header_len: usize) -> FrameState {
+        return FrameState {
+            magic,
+            legacy: true,
+            block_checksum_flag: false,
+            content_checksum_flag: false,
+            content_size: None,
+            linked: false,
+            history_size,
+            history_len: 0,
+            block_max_len,
+            compressed_max_len,
+            block: Vec::new(),
+            out_block: Vec::new(),
+            stored: false,
+            out_len: 0,
+            content_hash: XXHash32::new(0),
+            decompressed_size: 0,
+            frame_pos,
+            in_pos: frame_pos + header_len,
+            block_index: 0,
+            done: false,
+            next_magic: None,
+        };
+    }
+
+    // Decompress the next block. Returns false at the end of the frame.
+    pub(crate) fn next_block<R: Read>(&mut self, reader: &mut R) -> Result<bool, Error> {
+        if self.linked {
+            let end = self.history_len + self.out_len;
+            let keep = min(end, self.history_size);
+            self.out_block.copy_within(end - keep..end, 0);
+            self.history_len = keep;
+        }
+        self.stored = false;
+        self.out_len = 0;
+        if self.done {
+            return Ok(false);
+        }
+        let mut header: [u8; 4] = [0; 4];
+        let mut block_size;
+        if self.legacy {
+            let read = read_fully(&mut *reader, &mut header)?;
+            if read == 0 {
+                self.done = true;
+                return Ok(false);
+            }
+            if read < 4 {
+                return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated block header")));
+            }
+            block_size = read_u32_le(&header, 0) as usize;
+            if block_size > self.compressed_max_len {
+                // can not be a block: this is the next frame
+                self.next_magic = Some(block_size as u32);
+                self.done = true;
+                return Ok(false);
+            }
+        } else {
+            reader.read_exact(&mut header)?;
+            block_size = read_u32_le(&header, 0) as usize;
+            if block_size == 0 {
+                self.done = true;
+                self.read_end(reader)?;
+                return Ok(false);
+            }
+            self.stored = ((block_size >> 31) & 1) == 1;
+            block_size &= 0x7fffffff;
+            if block_size > self.block_max_len {
+                return Err(Error::CorruptBlock { offset: self.in_pos, message: "Block too large" });
+            }
+        }
+        if self.block.len() < block_size {
+            self.block.resize(block_size, 0);
+        }
+        // a compressed byte can expand to at most 255 bytes
+        let max_len = if self.stored { block_size } else { min(self.block_max_len, 255 * block_size) };
+        if self.out_block.len() < self.history_len + max_len {
+            self.out_block.resize(self.history_len + max_len, 0);
+        }
+        reader.read_exact(&mut self.block[0..block_size])?;
+        if self.block_checksum_flag {
+            reader.read_exact(&mut header)?;
+            let block_checksum = read_u32_le(&header, 0);
+            let mut block_hash = XXHash32::new(0);
+            if block_checksum != block_hash.update(&self.block[0..block_size]) {
+                return Err(Error::BlockChecksum { block_index: self.block_index, offset: self.in_pos });
+            }
+        }
+        let start = self.history_len;
+        if self.stored {
+            self.out_len = block_size;
+            if self.linked {
+                self.out_block[start..start + block_size].copy_from_slice(&self.block[0..block_size]);
+            }
         } else {
-            let size = decompress_block(&block, block_size, &mut out_block, 0)?;
-            writer.write_all(&out_block[0..size])?;
-            output_file_size += size;
+            let block_pos = self.in_pos + 4;
+            let end = decompress_block(&self.block[0..block_size], &mut self.out_block, start).map_err(|e| {
+                return block_error(e, block_pos);
+            })?;
+            self.out_len = end - start;
+        }
+        if self.content_checksum_flag {
+            if self.stored {
+                self.content_hash.update(&self.block[0..block_size]);
+            } else {
+                self.content_hash.update(&self.out_block[start..start + self.out_len]);
+            }
         }
+        self.decompressed_size += self.out_len;
+        self.in_pos += 4 + block_size;
+        if self.block_checksum_flag {
+            self.in_pos += 4;
+        }
+        self.block_index += 1;
+        return Ok(true);
     }
-    drop(block);
-    drop(reader);
-    writer.flush()?;
-    drop(out_block);
-    drop(writer);
-    return Ok(output_file_size);
+
+    // Verify the content size and checksum, after the end mark was read.
+    fn read_end<R: Read>(&mut self, reader: &mut R) -> Result<(), Error> {
+        if let Some(content_size) = self.content_size {
+            if self.decompressed_size as u64 != content_size {
+                return Err(Error::ContentSize { expected: content_size, actual: self.decompressed_size as u64 });
+            }
+        }
+        self.in_pos += 4;
+        if self.content_checksum_flag {
+            let mut header: [u8; 4] = [0; 4];
+            reader.read_exact(&mut header)?;
+            if read_u32_le(&header, 0) != self.content_hash.digest() {
+                return Err(Error::ContentChecksum);
+            }
+            self.in_pos += 4;
+        }
+        return Ok(());
+    }
+
+    // The decompressed data of the current block.
+    pub(crate) fn output(&self) -> &[u8] {
+        if self.stored {
+            return &self.block[0..self.out_len];
+        }
+        return &self.out_block[self.history_len..self.history_len + self.out_len];
+    }
+
+    // The position in the compressed stream after the current block.
+    pub(crate) fn stream_pos(&self) -> usize {
+        return self.in_pos;
+    }
+
+    pub(crate) fn info(&self) -> FrameInfo {
+        return FrameInfo {
+            magic: self.magic,
+            compressed_size: self.in_pos - self.frame_pos,
+            decompressed_size: self.decompressed_size,
+            block_count: self.block_index,
+        };
+    }
+}
+
+/// Whether the magic number is one of a skippable frame
+/// (0x184D2A50 to 0x184D2A5F).
+pub fn is_skippable_magic(magic: u32) -> bool {
+    return magic & 0xfffffff0 == 0x184D2A50;
+}
+
+/// Decompress in_data into out_data, starting at position o. Matches may
+/// refer to out_data[0..o]. Returns the end position in out_data.
+pub fn decompress_block(in_data: &[u8], out_data: &mut [u8], o: usize) -> Result<usize, Error> {
+    return decompress_block_with_dictionary(in_data, &[], out_data, o);
+}
+
+/// Decompress a block into the output buffer. Returns the number of
+/// decompressed bytes.
+pub fn decompress_block_into(input: &[u8], output: &mut [u8]) -> Result<usize, Error> {
+    return decompress_block_with_dictionary(input, &[], output, 0);
+}
+
+/// Decompress a block that was compressed with a dictionary. Matches may
+/// refer to the dictionary, which logically precedes out_data.
+pub fn decompress_block_with_dictionary(in_data: &[u8], dictionary: &[u8], out_data: &mut [u8], o: usize) -> Result<usize, Error> {
+    let out_len = out_data.len();
+    let (end, _) = decode_block(in_data, dictionary, out_data, o, out_len, false)?;
+    return Ok(end);
 }
 
-pub fn decompress_block(in_data: &Vec<u8>, in_len: usize, out_data: &mut Vec<u8>, o: usize) -> Result<usize, Error> {
-    if in_len > in_data.len() {
-        return error("Input buffer too small");
+/// Decompress a block into the output buffer, but stop as soon as
+/// target_len bytes are decompressed (like LZ4_decompress_safe_partial).
+/// The input may be truncated after that point. Returns the number of
+/// decompressed bytes (less than target_len if the block is shorter),
+/// and the number of input bytes that were used.
+pub fn decompress_block_partial(in_data: &[u8], out_data: &mut [u8], target_len: usize) -> Result<(usize, usize), Error> {
+    let stop = min(target_len, out_data.len());
+    return decode_block(in_data, &[], out_data, 0, stop, true);
+}
+
+// Decompress a block, starting at position o. With partial, decompression
+// stops at position stop, otherwise the output must not be larger than stop.
+// Returns the end position in out_data, and in in_data.
+fn decode_block(in_data: &[u8], dictionary: &[u8], out_data: &mut [u8], o: usize, stop: usize, partial: bool) -> Result<(usize, usize), Error> {
+    let in_len = in_data.len();
+    if o > stop {
+        return Err(Error::OutputTooSmall);
     }
     let mut out_pos: usize = o;
     let mut p = 0;
     loop {
+        if partial && out_pos == stop {
+            return Ok((out_pos, p));
+        }
+        if p >= in_len {
+            return Err(Error::CorruptBlock { offset: p, message: "Truncated block" });
+        }
         let tag = in_data[p];
         p += 1;
         let mut literal_len: usize = tag as usize >> 4;
         if literal_len == 0xf {
             loop {
+                if p >= in_len {
+                    return Err(Error::CorruptBlock { offset: p, message: "Truncated literal length" });
+                }
                 let x = in_data[p] as usize;
                 p += 1;
                 literal_len += x;
@@ -121,33 +553,50 @@ pub fn decompress_block(in_data: &Vec<u8>, in_len: usize, out_data: &mut Vec<u8>
                 }
             }
         }
-        for i in 0..literal_len {
-            out_data[out_pos + i] = in_data[p + i];
+        let mut copy_len = literal_len;
+        if partial && copy_len > stop - out_pos {
+            copy_len = stop - out_pos;
+        }
+        if copy_len > in_len - p {
+            return Err(Error::CorruptBlock { offset: p, message: "Literal run too long" });
         }
-        out_pos += literal_len;
-        // println!("    out_pos={out_pos} literal_len={literal_len}");
-        p += literal_len;
-        if p + 1 >= in_len {
-            // println!("end out_pos={out_pos} p={p} literal_len={literal_len}");
+        if copy_len > stop - out_pos {
+            return Err(Error::OutputTooSmall);
+        }
+        if copy_len <= 16 && in_len - p >= 16 && stop - out_pos >= 16 {
+            // copy 16 bytes; the bytes after the literals are overwritten later
+            let chunk: [u8; 16] = in_data[p..p + 16].try_into().unwrap();
+            out_data[out_pos..out_pos + 16].copy_from_slice(&chunk);
+        } else {
+            out_data[out_pos..out_pos + copy_len].copy_from_slice(&in_data[p..p + copy_len]);
+        }
+        out_pos += copy_len;
+        p += copy_len;
+        if copy_len < literal_len {
+            return Ok((out_pos, p));
+        }
+        if p == in_len {
+            // the last sequence only has literals
             break;
         }
+        if p + 2 > in_len {
+            return Err(Error::CorruptBlock { offset: p, message: "Truncated offset" });
+        }
         let offset = ((in_data[p] as u32) |
             ((in_data[p + 1] as u32) << 8)) as usize;
-        // println!("    offset = {offset}");
         if offset == 0 {
-            return error("Offset 0");
+            return Err(Error::CorruptBlock { offset: p, message: "Offset 0" });
         }
-        if offset > out_pos {
-            return error("Offset too large");
+        if offset > out_pos + dictionary.len() {
+            return Err(Error::CorruptBlock { offset: p, message: "Offset too large" });
         }
         p += 2;
-        if p >= in_len - 1 {
-            // println!("end2 out_pos={out_pos} p={p} literal_len={literal_len}");
-            break;
-        }
         let mut run_len = tag as usize & 0xf;
         if run_len == 0xf {
             loop {
+                if p >= in_len {
+                    return Err(Error::CorruptBlock { offset: p, message: "Truncated match length" });
+                }
                 let x = in_data[p] as usize;
                 p += 1;
                 run_len += x;
@@ -157,11 +606,463 @@ pub fn decompress_block(in_data: &Vec<u8>, in_len: usize, out_data: &mut Vec<u8>
             }
         }
         run_len += 4;
-        for i in 0..run_len {
-            out_data[out_pos + i] = out_data[out_pos + i - offset];
+        if partial && run_len > stop - out_pos {
+            run_len = stop - out_pos;
+        }
+        if run_len > stop - out_pos {
+            return Err(Error::OutputTooSmall);
+        }
+        if offset > out_pos {
+            // the match starts in the dictionary
+            let dict_pos = dictionary.len() + out_pos - offset;
+            let dict_len = min(run_len, dictionary.len() - dict_pos);
+            out_data[out_pos..out_pos + dict_len].copy_from_slice(&dictionary[dict_pos..dict_pos + dict_len]);
+            out_pos += dict_len;
+            run_len -= dict_len;
+        }
+        if run_len > 0 {
+            copy_match(out_data, out_pos, offset, run_len, stop);
+            out_pos += run_len;
+        }
+    }
+    return Ok((out_pos, p));
+}
+
+// Copy run_len bytes from out_pos - offset to out_pos. The bytes after
+// that, up to stop, may be overwritten.
+fn copy_match(out_data: &mut [u8], out_pos: usize, offset: usize, run_len: usize, stop: usize) {
+    let start = out_pos - offset;
+    let end = out_pos + run_len;
+    if offset >= 16 && run_len <= 16 && out_pos + 16 <= stop {
+        let chunk: [u8; 16] = out_data[start..start + 16].try_into().unwrap();
+        out_data[out_pos..out_pos + 16].copy_from_slice(&chunk);
+        return;
+    }
+    if offset >= run_len && run_len > 32 {
+        out_data.copy_within(start..start + run_len, out_pos);
+        return;
+    }
+    let mut pos = out_pos;
+    if offset >= 8 {
+        // each chunk only reads bytes that were already written
+        while pos < end && pos + 8 <= stop {
+            let chunk: [u8; 8] = out_data[pos - offset..pos - offset + 8].try_into().unwrap();
+            out_data[pos..pos + 8].copy_from_slice(&chunk);
+            pos += 8;
+        }
+    } else {
+        // repeat the pattern; for offsets 1, 2 and 4 it fills 8 bytes,
+        // otherwise only whole repetitions are used
+        let mut pattern: [u8; 8] = [0; 8];
+        pattern[0..offset].copy_from_slice(&out_data[start..out_pos]);
+        for i in offset..8 {
+            pattern[i] = pattern[i - offset];
+        }
+        let step = [0, 8, 8, 6, 8, 5, 6, 7][offset];
+        while pos < end && pos + 8 <= stop {
+            out_data[pos..pos + 8].copy_from_slice(&pattern);
+            pos += step;
+        }
+    }
+    while pos < end {
+        out_data[pos] = out_data[pos - offset];
+        pos += 1;
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use crate::compress::compress;
+    use crate::xxhash32::write_u32_le;
+    use crate::compress::compress_with_options;
+    use crate::compress::CompressOptions;
+    use crate::compress::write_skippable_frame;
+    use crate::compress::compress_bound;
+    use crate::compress::Compress;
+    use super::*;
+
+    #[test]
+    fn content_checksum() {
+        let data = test_data(10000);
+        let mut compressed: Vec<u8> = Vec::new();
+        compress(&data[..], &mut compressed, 1).unwrap();
+        let mut test: Vec<u8> = Vec::new();
+        decompress(&compressed[..], &mut test).unwrap();
+        assert_eq!(test, data);
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+        let result = decompress(&compressed[..], &mut Vec::new());
+        assert!(matches!(result, Err(Error::ContentChecksum)));
+    }
+
+    #[test]
+    fn block_checksum() {
+        let data = test_data(10000);
+        let mut options = CompressOptions::new(1);
+        options.block_checksum = true;
+        let mut compressed: Vec<u8> = Vec::new();
+        compress_with_options(&data[..], &mut compressed, &options).unwrap();
+        let mut test: Vec<u8> = Vec::new();
+        decompress(&compressed[..], &mut test).unwrap();
+        assert_eq!(test, data);
+        compressed[20] ^= 1;
+        let result = decompress(&compressed[..], &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Block checksum mismatch in block 0 at offset 7");
+    }
+
+    #[test]
+    fn content_size() {
+        let data = test_data(10000);
+        let mut options = CompressOptions::new(1);
+        options.content_size = Some(data.len() as u64);
+        let mut compressed: Vec<u8> = Vec::new();
+        compress_with_options(&data[..], &mut compressed, &options).unwrap();
+        let mut test: Vec<u8> = Vec::new();
+        decompress(&compressed[..], &mut test).unwrap();
+        assert_eq!(test, data);
+        // change the content size, and fix the header checksum
+        compressed[6] ^= 1;
+        let mut hash = XXHash32::new(0);
+        compressed[14] = (hash.update(&compressed[4..14]) >> 8) as u8;
+        let result = decompress(&compressed[..], &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Content size mismatch: expected 10001, got 10000");
+    }
+
+    #[test]
+    fn linked_blocks() {
+        let text = "Hello world, this is a linked block test.".as_bytes().to_vec();
+        let mut frame: Vec<u8> = Vec::new();
+        frame.resize(7, 0);
+        write_u32_le(&mut frame, 0, 0x184D2204);
+        frame[4] = 1 << 6;
+        frame[5] = 7 << 4;
+        let mut hash = XXHash32::new(0);
+        frame[6] = (hash.update(&frame[4..6]) >> 8) as u8;
+        // first block: literals only
+        frame.extend_from_slice(&[text.len() as u8 + 2, 0, 0, 0]);
+        frame.extend_from_slice(&[0xf0, text.len() as u8 - 15]);
+        frame.extend_from_slice(&text);
+        // second block: a match of 10 bytes in the first block, then 5 literals
+        frame.extend_from_slice(&[9, 0, 0, 0]);
+        frame.extend_from_slice(&[0x06, text.len() as u8, 0]);
+        frame.extend_from_slice(&[0x50, b'1', b'2', b'3', b'4', b'5']);
+        frame.extend_from_slice(&[0, 0, 0, 0]);
+        let mut test: Vec<u8> = Vec::new();
+        let size = decompress(&frame[..], &mut test).unwrap();
+        let mut expected = text.clone();
+        expected.extend_from_slice(&text[0..10]);
+        expected.extend_from_slice("12345".as_bytes());
+        assert_eq!(size, expected.len());
+        assert_eq!(test, expected);
+    }
+
+    #[test]
+    fn dictionary() {
+        let mut dictionary: Vec<u8> = Vec::new();
+        for i in 0..1000 {
+            dictionary.push((i * 7 + i / 100) as u8);
+        }
+        let mut data: Vec<u8> = Vec::new();
+        for i in 0..100000 {
+            data.push(dictionary[(i * 3) % 1000 / 10 * 10 + i % 10]);
+        }
+        for linked_blocks in [false, true] {
+            let mut options = CompressOptions::new(1);
+            options.block_max_size = 4;
+            options.linked_blocks = linked_blocks;
+            options.dictionary = Some(dictionary.clone());
+            options.dictionary_id = Some(5);
+            let mut compressed: Vec<u8> = Vec::new();
+            compress_with_options(&data[..], &mut compressed, &options).unwrap();
+            let mut decompress_options = DecompressOptions::new();
+            let result = decompress_with_options(&compressed[..], &mut Vec::new(), &decompress_options);
+            assert_eq!(result.unwrap_err().to_string(), "Missing dictionary 5");
+            decompress_options.dictionary = Some(dictionary.clone());
+            decompress_options.dictionary_id = Some(6);
+            let result = decompress_with_options(&compressed[..], &mut Vec::new(), &decompress_options);
+            assert_eq!(result.unwrap_err().to_string(), "Dictionary id mismatch: expected 6, got 5");
+            decompress_options.dictionary_id = Some(5);
+            let mut test: Vec<u8> = Vec::new();
+            decompress_with_options(&compressed[..], &mut test, &decompress_options).unwrap();
+            assert_eq!(test, data, "linked {linked_blocks}");
+        }
+    }
+
+    #[test]
+    fn skippable_frames() {
+        let data = test_data(10000);
+        let mut compressed: Vec<u8> = Vec::new();
+        write_skippable_frame(&mut compressed, 0, "schema 1".as_bytes()).unwrap();
+        write_skippable_frame(&mut compressed, 15, &Vec::new()).unwrap();
+        compress(&data[..], &mut compressed, 1).unwrap();
+        let mut test: Vec<u8> = Vec::new();
+        let mut frames: Vec<(u32, Vec<u8>)> = Vec::new();
+        decompress_with_skippable_frames(&compressed[..], &mut test, &DecompressOptions::new(), |magic, data| {
+            frames.push((magic, data.to_vec()));
+        }).unwrap();
+        assert_eq!(test, data);
+        assert_eq!(frames, vec![(0x184D2A50, "schema 1".as_bytes().to_vec()), (0x184D2A5F, Vec::new())]);
+        // without a callback, the data of skippable frames is not kept
+        let size: u32 = 64 * 1024 * 1024;
+        let mut header: Vec<u8> = Vec::new();
+        header.extend_from_slice(&0x184D2A50u32.to_le_bytes());
+        header.extend_from_slice(&size.to_le_bytes());
+        let read = (&header[..]).chain(io::repeat(0).take(size as u64)).chain(&compressed[..]);
+        let mut test: Vec<u8> = Vec::new();
+        decompress(read, &mut test).unwrap();
+        assert_eq!(test, data);
+    }
+
+    #[test]
+    fn concatenated_frames() {
+        let data = test_data(10000);
+        let mut compressed: Vec<u8> = Vec::new();
+        let size1 = compress(&data[0..6000], &mut compressed, 1).unwrap();
+        let len1 = compressed.len();
+        write_skippable_frame(&mut compressed, 1, "metadata".as_bytes()).unwrap();
+        let size2 = compress(&data[6000..], &mut compressed, 9).unwrap();
+        let len2 = compressed.len() - len1 - 16;
+        let mut test: Vec<u8> = Vec::new();
+        let frames = decompress_with_frame_info(&compressed[..], &mut test, &DecompressOptions::new()).unwrap();
+        assert_eq!(test, data);
+        assert_eq!(frames, vec![
+            FrameInfo { magic: 0x184D2204, compressed_size: len1, decompressed_size: size1, block_count: 1 },
+            FrameInfo { magic: 0x184D2A51, compressed_size: 16, decompressed_size: 0, block_count: 0 },
+            FrameInfo { magic: 0x184D2204, compressed_size: len2, decompressed_size: size2, block_count: 1 },
+        ]);
+        compressed.push(0x04);
+        let result = decompress(&compressed[..], &mut Vec::new());
+        assert_eq!(result.unwrap_err().to_string(), "Truncated frame header");
+    }
+
+    #[test]
+    fn legacy_frames() {
+        let data = test_data(10000);
+        let mut options = CompressOptions::new(1);
+        options.legacy_format = true;
+        let mut compressed: Vec<u8> = Vec::new();
+        compress_with_options(&data[0..6000], &mut compressed, &options).unwrap();
+        assert_eq!(read_u32_le(&compressed, 0), 0x184C2102);
+        let len1 = compressed.len();
+        compress(&data[6000..], &mut compressed, 1).unwrap();
+        compress_with_options(&data[0..0], &mut compressed, &options).unwrap();
+        let mut test: Vec<u8> = Vec::new();
+        let frames = decompress_with_frame_info(&compressed[..], &mut test, &DecompressOptions::new()).unwrap();
+        assert_eq!(test, data);
+        assert_eq!(frames.len(), 3);
+        assert_eq!(frames[0], FrameInfo { magic: 0x184C2102, compressed_size: len1, decompressed_size: 6000, block_count: 1 });
+        assert_eq!(frames[2], FrameInfo { magic: 0x184C2102, compressed_size: 4, decompressed_size: 0, block_count: 0 });
+    }
+
+    // Data with short repeated patterns, that changes slowly.
+    pub(crate) fn test_data(len: usize) -> Vec<u8> {
+        let mut data: Vec<u8> = Vec::new();
+        for i in 0..len {
+            data.push((i % 100 + i / 1000) as u8);
+        }
+        return data;
+    }
+
+    pub(crate) fn random_bytes(data: &mut Vec<u8>, len: usize, seed: u64) {
+        let mut x = seed | 1;
+        for _ in 0..len {
+            x ^= x << 13;
+            x ^= x >> 7;
+            x ^= x << 17;
+            data.push(x as u8);
+        }
+    }
+
+    fn round_trip_small_blocks(data: &Vec<u8>, level: usize, linked_blocks: bool) {
+        let mut options = CompressOptions::new(level);
+        options.block_max_size = 4;
+        options.linked_blocks = linked_blocks;
+        options.block_checksum = true;
+        let mut compressed: Vec<u8> = Vec::new();
+        compress_with_options(&data[..], &mut compressed, &options).unwrap();
+        let mut test: Vec<u8> = Vec::new();
+        let frames = decompress_with_frame_info(&compressed[..], &mut test, &DecompressOptions::new()).unwrap();
+        assert_eq!(frames[0].block_count, 4);
+        assert_eq!(test.len(), data.len(), "level {level} linked {linked_blocks}");
+        assert!(test == *data, "level {level} linked {linked_blocks}");
+    }
+
+    #[test]
+    fn stored_and_compressed_blocks() {
+        // compressed, stored, compressed, and a short stored block
+        for tail in [1, 5, 13, 20, 40000] {
+            let mut data = test_data(64 * 1024);
+            random_bytes(&mut data, 64 * 1024, 1);
+            for i in 0..64 * 1024 {
+                data.push((i % 251) as u8 ^ (i / 1000) as u8);
+            }
+            random_bytes(&mut data, tail, 2);
+            for level in [1, 2] {
+                round_trip_small_blocks(&data, level, false);
+                round_trip_small_blocks(&data, level, true);
+            }
+            if tail == 5 {
+                round_trip_small_blocks(&data, 9, false);
+            }
+        }
+    }
+
+    #[test]
+    fn output_limits() {
+        let mut data: Vec<u8> = Vec::new();
+        data.resize(1024 * 1024, 0);
+        let mut options = CompressOptions::new(1);
+        options.block_max_size = 4;
+        let mut compressed: Vec<u8> = Vec::new();
+        compress_with_options(&data[..], &mut compressed, &options).unwrap();
+        let mut options = DecompressOptions::new();
+        options.max_output_size = Some(data.len() as u64);
+        let mut test: Vec<u8> = Vec::new();
+        decompress_with_options(&compressed[..], &mut test, &options).unwrap();
+        assert_eq!(test, data);
+        // the data of the last block is not written
+        options.max_output_size = Some(data.len() as u64 - 1);
+        let mut test: Vec<u8> = Vec::new();
+        let result = decompress_with_options(&compressed[..], &mut test, &options);
+        assert!(matches!(result, Err(Error::OutputLimit { size: 1048576, limit: 1048575 })));
+        assert_eq!(test.len(), data.len() - 64 * 1024);
+        // zeros compress with a ratio of about 250
+        let mut options = DecompressOptions::new();
+        options.max_ratio = Some(300);
+        decompress_with_options(&compressed[..], &mut Vec::new(), &options).unwrap();
+        options.max_ratio = Some(100);
+        let result = decompress_with_options(&compressed[..], &mut Vec::new(), &options);
+        assert!(matches!(result, Err(Error::OutputLimit { size: 65536, .. })));
+    }
+
+    #[test]
+    fn malformed_blocks() {
+        let mut out_block: Vec<u8> = Vec::new();
+        out_block.resize(100, 0);
+        let cases: [(&[u8], &str); 8] = [
+            (&[], "Truncated block at offset 0"),
+            (&[0xf0], "Truncated literal length at offset 1"),
+            (&[0xf0, 0xff], "Truncated literal length at offset 2"),
+            (&[0x30, 1, 2], "Literal run too long at offset 1"),
+            (&[0x10, 1, 1], "Truncated offset at offset 2"),
+            (&[0x10, 1, 0, 0, 0x00], "Offset 0 at offset 2"),
+            (&[0x10, 1, 2, 0, 0x00], "Offset too large at offset 2"),
+            (&[0x1f, 1, 1, 0], "Truncated match length at offset 4"),
+        ];
+        for (data, message) in cases {
+            let data = data.to_vec();
+            let result = decompress_block(&data, &mut out_block, 0);
+            assert_eq!(result.unwrap_err().to_string(), message, "{data:?}");
+        }
+        let data = [0x1f, 1, 1, 0, 200, 0x00].to_vec();
+        let result = decompress_block(&data, &mut out_block, 0);
+        assert!(matches!(result, Err(Error::OutputTooSmall)));
+        let data = [0x10, 1, 1, 0, 0x00].to_vec();
+        assert_eq!(decompress_block(&data, &mut out_block, 0).unwrap(), 5);
+        let data = [0x70, 1, 2, 3, 4, 5, 6, 7].to_vec();
+        let result = decompress_block(&data, &mut out_block, 95);
+        assert!(matches!(result, Err(Error::OutputTooSmall)));
+    }
+
+    #[test]
+    fn partial_blocks() {
+        let mut data: Vec<u8> = Vec::new();
+        for i in 0..2000 {
+            data.push((i % 100 + i / 300) as u8);
+        }
+        random_bytes(&mut data, 100, 3);
+        let mut block: Vec<u8> = Vec::new();
+        block.resize(compress_bound(data.len()), 0);
+        let end = Compress::new(data.len(), 1).compress_block(&data, &mut block, 0).unwrap();
+        let block = &block[0..end];
+        let mut out_block: Vec<u8> = Vec::new();
+        out_block.resize(data.len(), 0);
+        let mut last_used = 0;
+        for target_len in [0, 1, 10, 100, 150, 500, 2000, 2099, 2100] {
+            let (len, used) = decompress_block_partial(block, &mut out_block, target_len).unwrap();
+            assert_eq!(len, target_len);
+            assert_eq!(out_block[0..len], data[0..len]);
+            assert!(used >= last_used && used <= block.len());
+            last_used = used;
+            // the rest of the input is not needed
+            let (len, _) = decompress_block_partial(&block[0..used], &mut out_block, target_len).unwrap();
+            assert_eq!(len, target_len);
+        }
+        assert_eq!(last_used, block.len());
+        // the block is shorter than the target
+        let (len, used) = decompress_block_partial(block, &mut out_block, 3000).unwrap();
+        assert_eq!((len, used), (data.len(), block.len()));
+        let (len, _) = decompress_block_partial(block, &mut out_block[0..50], 3000).unwrap();
+        assert_eq!(len, 50);
+        let result = decompress_block_partial(&block[0..10], &mut out_block, 2000);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn match_copies() {
+        let mut literals: Vec<u8> = Vec::new();
+        random_bytes(&mut literals, 25, 7);
+        for offset in 1..21 {
+            for run_len in 4..41 {
+                // 20 literals, a match, and 5 literals
+                let mut block: Vec<u8> = Vec::new();
+                block.push(0xf0 | min(run_len - 4, 15) as u8);
+                block.push(5);
+                block.extend_from_slice(&literals[0..20]);
+                block.push(offset as u8);
+                block.push(0);
+                if run_len - 4 >= 15 {
+                    block.push((run_len - 4 - 15) as u8);
+                }
+                block.push(0x50);
+                block.extend_from_slice(&literals[20..25]);
+                let mut expected = literals[0..20].to_vec();
+                for _ in 0..run_len {
+                    expected.push(expected[expected.len() - offset]);
+                }
+                expected.extend_from_slice(&literals[20..25]);
+                for slack in [0, 40] {
+                    let mut out_block: Vec<u8> = Vec::new();
+                    out_block.resize(expected.len() + slack, 0);
+                    let len = decompress_block(&block, &mut out_block, 0).unwrap();
+                    assert_eq!(out_block[0..len], expected, "offset {offset} run_len {run_len}");
+                }
+                let mut out_block: Vec<u8> = Vec::new();
+                out_block.resize(expected.len(), 0);
+                let (len, _) = decompress_block_partial(&block, &mut out_block, 21 + run_len / 2).unwrap();
+                assert_eq!(out_block[0..len], expected[0..len]);
+                assert_eq!(out_block[len..], vec![0; expected.len() - len]);
+            }
+        }
+    }
+
+    #[test]
+    fn corrupt_frames() {
+        let mut data: Vec<u8> = Vec::new();
+        for i in 0..2000 {
+            data.push((i % 100 + i / 300) as u8);
+        }
+        random_bytes(&mut data, 100, 3);
+        let mut options = CompressOptions::new(1);
+        options.content_checksum = false;
+        options.block_max_size = 4;
+        let mut compressed: Vec<u8> = Vec::new();
+        compress_with_options(&data[..], &mut compressed, &options).unwrap();
+        // truncated
+        for len in 0..compressed.len() {
+            let result = decompress(&compressed[0..len], &mut Vec::new());
+            assert!(result.is_err(), "len {len}");
+        }
+        // not a frame
+        let result = decompress_to_vec("abcd".as_bytes());
+        assert!(matches!(result, Err(Error::BadMagic(0x64636261))));
+        // corrupt (without checksums, this may or may not be detected)
+        for pos in 7..compressed.len() {
+            for bit in 0..8 {
+                let mut corrupt = compressed.clone();
+                corrupt[pos] ^= 1 << bit;
+                let _ = decompress(&corrupt[..], &mut Vec::new());
+            }
         }
-        out_pos += run_len;
-        // println!("    p={p} out_pos={out_pos} run_len={run_len} offset={offset}");
     }
-    return Ok(out_pos);
 }
@@ -0,0 +1,107 @@
+use std::fmt;
+use std::io;
+
+/// The error type of all compression, decompression and hashing functions.
+#[derive(Debug)]
+pub enum Error {
+    /// Reading or writing failed, or the input ended unexpectedly.
+    Io(io::Error),
+    /// The magic number is not the one of a known frame format.
+    BadMagic(u32),
+    /// The frame (or the options) use a feature that is not supported.
+    Unsupported(String),
+    /// The frame header checksum does not match.
+    HeaderChecksum,
+    /// The checksum of a block does not match. The offset is the position
+    /// of the block in the compressed stream.
+    BlockChecksum { block_index: usize, offset: usize },
+    /// The content checksum does not match.
+    ContentChecksum,
+    /// The content size in the frame header (or the options) does not match.
+    ContentSize { expected: u64, actual: u64 },
+    /// The frame was compressed with a dictionary, but none was given.
+    MissingDictionary(u32),
+    /// The frame was compressed with a different dictionary.
+    DictionaryId { expected: u32, actual: u32 },
+    /// The compressed data is corrupt. The offset is the position in the
+    /// compressed block (for block functions) or stream (for frame functions).
+    CorruptBlock { offset: usize, message: &'static str },
+    /// The output buffer is too small.
+    OutputTooSmall,
+    /// The decompressed data is larger than the configured maximum size
+    /// or ratio.
+    OutputLimit { size: u64, limit: u64 },
+    /// An argument is invalid, for example the input length is larger than the buffer.
+    InvalidInput(&'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            Error::Io(e) => write!(f, "{e}"),
+            Error::BadMagic(magic) => write!(f, "Incorrect magic {magic:#010x}"),
+            Error::Unsupported(feature) => write!(f, "Unsupported {feature}"),
+            Error::HeaderChecksum => write!(f, "Header checksum mismatch"),
+            Error::BlockChecksum { block_index, offset } =>
+                write!(f, "Block checksum mismatch in block {block_index} at offset {offset}"),
+            Error::ContentChecksum => write!(f, "Content checksum mismatch"),
+            Error::ContentSize { expected, actual } =>
+                write!(f, "Content size mismatch: expected {expected}, got {actual}"),
+            Error::MissingDictionary(id) => write!(f, "Missing dictionary {id}"),
+            Error::DictionaryId { expected, actual } =>
+                write!(f, "Dictionary id mismatch: expected {expected}, got {actual}"),
+            Error::CorruptBlock { offset, message } => write!(f, "{message} at offset {offset}"),
+            Error::OutputTooSmall => write!(f, "Output buffer too small"),
+            Error::OutputLimit { size, limit } =>
+                write!(f, "Output size {size} exceeds the limit of {limit}"),
+            Error::InvalidInput(message) => write!(f, "{message}"),
+        };
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        return match self {
+            Error::Io(e) => Some(e),
+            _ => None,
+        };
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        // an error of this crate that was converted to an io::Error
+        if e.get_ref().is_some_and(|inner| inner.is::<Error>()) {
+            return *e.into_inner().unwrap().downcast::<Error>().unwrap();
+        }
+        return Error::Io(e);
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(e: Error) -> io::Error {
+        let kind = match e {
+            Error::Io(e) => return e,
+            Error::Unsupported(_) => io::ErrorKind::Unsupported,
+            Error::OutputTooSmall | Error::InvalidInput(_) => io::ErrorKind::InvalidInput,
+            _ => io::ErrorKind::InvalidData,
+        };
+        return io::Error::new(kind, e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn io_error_conversion() {
+        let e: io::Error = Error::ContentChecksum.into();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+        assert!(matches!(Error::from(e), Error::ContentChecksum));
+        let e = Error::from(io::Error::from(io::ErrorKind::UnexpectedEof));
+        assert!(matches!(&e, Error::Io(x) if x.kind() == io::ErrorKind::UnexpectedEof));
+        let e: io::Error = e.into();
+        assert_eq!(e.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,211 @@
+use std::cmp::min;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::io;
+
+use crate::decompress::DecompressOptions;
+use crate::decompress::FrameState;
+use crate::decompress::is_skippable_magic;
+use crate::error::Error;
+use crate::xxhash32::read_fully;
+use crate::xxhash32::read_u32_le;
+
+/// Decompresses a stream of frames while it is read. The data is
+/// decompressed one block at a time, when needed. Skippable frames are
+/// skipped.
+pub struct FrameDecoder<R: Read> {
+    reader: BufReader<R>,
+    options: DecompressOptions,
+    // the frame that is currently decompressed
+    frame: Option<FrameState>,
+    // the number of bytes of the current block that were read
+    pos: usize,
+    // the position of the current frame in the stream
+    frame_pos: usize,
+    frame_count: usize,
+    output_size: usize,
+    // the magic number of the next frame, if already read
+    next_magic: Option<u32>,
+}
+
+impl<R: Read> FrameDecoder<R> {
+    pub fn new(read: R) -> FrameDecoder<R> {
+        return FrameDecoder::with_options(read, DecompressOptions::new());
+    }
+
+    pub fn with_options(read: R, options: DecompressOptions) -> FrameDecoder<R> {
+        FrameDecoder {
+            reader: BufReader::new(read),
+            options,
+            frame: None,
+            pos: 0,
+            frame_pos: 0,
+            frame_count: 0,
+            output_size: 0,
+            next_magic: None,
+        }
+    }
+
+    /// Get the underlying reader. Data that was already read from it, but
+    /// not decompressed yet, is lost.
+    pub fn into_inner(self) -> R {
+        return self.reader.into_inner();
+    }
+
+    // Decompress the next block. Returns false at the end of the stream.
+    fn next_block(&mut self) -> Result<bool, Error> {
+        loop {
+            if let Some(frame) = &mut self.frame {
+                if frame.next_block(&mut self.reader)? {
+                    self.output_size += frame.output().len();
+                    self.options.check_output_size(frame.stream_pos(), self.output_size)?;
+                    self.pos = 0;
+                    return Ok(true);
+                }
+                self.next_magic = frame.next_magic;
+                self.frame_pos += frame.info().compressed_size;
+                self.frame = None;
+            }
+            let mut header: [u8; 4] = [0; 4];
+            let magic;
+            if let Some(m) = self.next_magic.take() {
+                magic = m;
+            } else {
+                let read = read_fully(&mut self.reader, &mut header)?;
+                if read == 0 && self.frame_count > 0 {
+                    return Ok(false);
+                }
+                if read < 4 {
+                    return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated frame header")));
+                }
+                magic = read_u32_le(&header, 0);
+            }
+            self.frame_count += 1;
+            if is_skippable_magic(magic) {
+                self.reader.read_exact(&mut header)?;
+                let size = read_u32_le(&header, 0) as u64;
+                let skipped = io::copy(&mut (&mut self.reader).take(size), &mut io::sink())?;
+                if skipped != size {
+                    return Err(Error::Io(io::Error::new(io::ErrorKind::UnexpectedEof, "Truncated skippable frame")));
+                }
+                self.frame_pos += 8 + size as usize;
+            } else {
+                self.frame = Some(FrameState::new(&mut self.reader, magic, &self.options, self.frame_pos)?);
+            }
+        }
+    }
+}
+
+impl<R: Read> BufRead for FrameDecoder<R> {
+    fn fill_buf(&mut self) -> io::Result<&[u8]> {
+        loop {
+            if let Some(frame) = &self.frame {
+                if self.pos < frame.output().len() {
+                    break;
+                }
+            }
+            if !self.next_block()? {
+                return Ok(&[]);
+            }
+        }
+        let frame = self.frame.as_ref().unwrap();
+        return Ok(&frame.output()[self.pos..]);
+    }
+
+    fn consume(&mut self, amt: usize) {
+        self.pos += amt;
+    }
+}
+
+impl<R: Read> Read for FrameDecoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let data = self.fill_buf()?;
+        let len = min(buf.len(), data.len());
+        buf[0..len].copy_from_slice(&data[0..len]);
+        self.consume(len);
+        return Ok(len);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::compress;
+    use crate::compress::compress_with_options;
+    use crate::compress::write_skippable_frame;
+    use crate::compress::CompressOptions;
+
+    #[test]
+    fn read_in_small_chunks() {
+        let mut data: Vec<u8> = Vec::new();
+        for i in 0..100000 {
+            data.push((i % 100 + i / 3000) as u8);
+        }
+        let mut options = CompressOptions::new(1);
+        options.block_max_size = 4;
+        options.linked_blocks = true;
+        let mut compressed: Vec<u8> = Vec::new();
+        compress_with_options(&data[..], &mut compressed, &options).unwrap();
+        // followed by a skippable and a legacy frame
+        write_skippable_frame(&mut compressed, 0, "hello".as_bytes()).unwrap();
+        options.legacy_format = true;
+        compress_with_options(&data[0..1000], &mut compressed, &options).unwrap();
+        let mut expected = data.clone();
+        expected.extend_from_slice(&data[0..1000]);
+        let mut decoder = FrameDecoder::new(&compressed[..]);
+        let mut test: Vec<u8> = Vec::new();
+        let mut buf: [u8; 7] = [0; 7];
+        loop {
+            let read = decoder.read(&mut buf).unwrap();
+            if read == 0 {
+                break;
+            }
+            test.extend_from_slice(&buf[0..read]);
+        }
+        assert_eq!(test, expected);
+        assert_eq!(decoder.read(&mut buf).unwrap(), 0);
+    }
+
+    #[test]
+    fn read_lines() {
+        let mut data: Vec<u8> = Vec::new();
+        for i in 0..1000 {
+            data.extend_from_slice(format!("line {i}\n").as_bytes());
+        }
+        let mut compressed: Vec<u8> = Vec::new();
+        compress(&data[..], &mut compressed, 1).unwrap();
+        let decoder = FrameDecoder::new(&compressed[..]);
+        let lines: Vec<String> = decoder.lines().map(|x| x.unwrap()).collect();
+        assert_eq!(lines.len(), 1000);
+        assert_eq!(lines[999], "line 999");
+    }
+
+    #[test]
+    fn read_corrupt() {
+        let data = "Hello world, hello world".as_bytes().to_vec();
+        let mut compressed: Vec<u8> = Vec::new();
+        compress(&data[..], &mut compressed, 1).unwrap();
+        let last = compressed.len() - 1;
+        compressed[last] ^= 1;
+        let mut decoder = FrameDecoder::new(&compressed[..]);
+        let e = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(matches!(Error::from(e), Error::ContentChecksum));
+        // not a frame
+        let mut decoder = FrameDecoder::new("abcd".as_bytes());
+        let e = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(matches!(Error::from(e), Error::BadMagic(0x64636261)));
+    }
+
+    #[test]
+    fn read_with_output_limit() {
+        let mut data: Vec<u8> = Vec::new();
+        data.resize(100000, 0);
+        let mut compressed: Vec<u8> = Vec::new();
+        compress(&data[..], &mut compressed, 1).unwrap();
+        let mut options = DecompressOptions::new();
+        options.max_output_size = Some(1000);
+        let mut decoder = FrameDecoder::with_options(&compressed[..], options);
+        let e = decoder.read_to_end(&mut Vec::new()).unwrap_err();
+        assert!(matches!(Error::from(e), Error::OutputLimit { size: 100000, limit: 1000 }));
+    }
+}
@@ -0,0 +1,133 @@
+use std::cmp::min;
+use std::io::prelude::*;
+use std::io;
+
+use crate::compress::CompressOptions;
+use crate::compress::FrameState;
+use crate::error::Error;
+
+/// Compresses the data written to it into a frame. The data is buffered
+/// and compressed one block at a time. The frame is only complete after
+/// calling finish.
+pub struct FrameEncoder<W: Write> {
+    writer: W,
+    frame: FrameState,
+    header_written: bool,
+    // the number of bytes in the input buffer of the frame
+    len: usize,
+}
+
+impl<W: Write> FrameEncoder<W> {
+    pub fn new(write: W, level: usize) -> FrameEncoder<W> {
+        return FrameEncoder::with_options(write, &CompressOptions::new(level)).unwrap();
+    }
+
+    pub fn with_options(write: W, options: &CompressOptions) -> Result<FrameEncoder<W>, Error> {
+        return Ok(FrameEncoder {
+            writer: write,
+            frame: FrameState::new(options, usize::MAX)?,
+            header_written: false,
+            len: 0,
+        });
+    }
+
+    // Compress and write the buffered data, if any.
+    fn write_block(&mut self) -> Result<(), Error> {
+        if !self.header_written {
+            self.writer.write_all(self.frame.header())?;
+            self.header_written = true;
+        }
+        if self.len > 0 {
+            let len = self.len;
+            self.len = 0;
+            self.writer.write_all(self.frame.compress_block(len)?)?;
+        }
+        return Ok(());
+    }
+
+    /// Compress the remaining data, write the end mark and the checksum,
+    /// and return the underlying writer.
+    pub fn finish(mut self) -> Result<W, Error> {
+        self.write_block()?;
+        self.writer.write_all(self.frame.end()?)?;
+        self.writer.flush()?;
+        return Ok(self.writer);
+    }
+}
+
+impl<W: Write> Write for FrameEncoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let input = self.frame.input();
+        let len = min(buf.len(), input.len() - self.len);
+        input[self.len..self.len + len].copy_from_slice(&buf[0..len]);
+        self.len += len;
+        if self.len == input.len() {
+            self.write_block()?;
+        }
+        return Ok(len);
+    }
+
+    /// Compress and write the buffered data as a (possibly small) block,
+    /// and flush the underlying writer.
+    fn flush(&mut self) -> io::Result<()> {
+        self.write_block()?;
+        return self.writer.flush();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::compress::compress_with_options;
+    use crate::decompress::decompress;
+
+    #[test]
+    fn write_in_small_chunks() {
+        let mut data: Vec<u8> = Vec::new();
+        for i in 0..100000 {
+            data.push((i % 100 + i / 3000) as u8);
+        }
+        for linked in [false, true] {
+            let mut options = CompressOptions::new(1);
+            options.block_max_size = 4;
+            options.linked_blocks = linked;
+            options.block_checksum = true;
+            let mut encoder = FrameEncoder::with_options(Vec::new(), &options).unwrap();
+            for chunk in data.chunks(7) {
+                encoder.write_all(chunk).unwrap();
+            }
+            let compressed = encoder.finish().unwrap();
+            // the same as when compressing all at once
+            let mut expected: Vec<u8> = Vec::new();
+            compress_with_options(&data[..], &mut expected, &options).unwrap();
+            assert_eq!(compressed, expected);
+        }
+    }
+
+    #[test]
+    fn flush() {
+        let mut encoder = FrameEncoder::new(Vec::new(), 1);
+        encoder.write_all("Hello ".as_bytes()).unwrap();
+        encoder.flush().unwrap();
+        let len = encoder.writer.len();
+        // the header and the first block
+        assert_eq!(len, 7 + 4 + 6);
+        encoder.write_all("world".as_bytes()).unwrap();
+        encoder.flush().unwrap();
+        encoder.flush().unwrap();
+        let compressed = encoder.finish().unwrap();
+        let mut test: Vec<u8> = Vec::new();
+        decompress(&compressed[..], &mut test).unwrap();
+        assert_eq!(test, "Hello world".as_bytes());
+    }
+
+    #[test]
+    fn content_size() {
+        let mut options = CompressOptions::new(1);
+        options.content_size = Some(12);
+        let mut encoder = FrameEncoder::with_options(Vec::new(), &options).unwrap();
+        encoder.write_all("Hello world".as_bytes()).unwrap();
+        let result = encoder.finish();
+        assert!(matches!(result, Err(Error::ContentSize { expected: 12, actual: 11 })));
+    }
+}
@@ -0,0 +1,56 @@
+//! A very simple LZ4 implementation.
+//!
+//! Frames are compressed and decompressed over `Read` / `Write`
+//! (see [`compress`] and [`decompress`]), or written with a [`FrameEncoder`]
+//! and read with a [`FrameDecoder`];
+//! single blocks can be processed with [`Compress::compress_block`] and
+//! [`decompress_block`].
+//! The content checksum uses [`XXHash32`].
+
+#![forbid(unsafe_code)]
+#![allow(clippy::needless_return)]
+#![allow(clippy::slow_vector_initialization)]
+#![allow(clippy::needless_range_loop)]
+#![allow(clippy::manual_memcpy)]
+#![allow(clippy::manual_range_contains)]
+#![allow(clippy::collapsible_if)]
+
+mod error;
+mod xxhash32;
+mod compress;
+mod decompress;
+mod frame_decoder;
+mod frame_encoder;
+
+pub use crate::error::Error;
+pub use crate::xxhash32::XXHash32;
+pub use crate::xxhash32::xxhash32;
+pub use crate::xxhash32::xxhash32_file;
+pub use crate::xxhash32::xxhash32_stream;
+pub use crate::compress::Compress;
+pub use crate::compress::CompressOptions;
+pub use crate::compress::compress;
+pub use crate::compress::compress_with_options;
+pub use crate::compress::compress_to_vec;
+pub use crate::compress::compress_bound;
+pub use crate::compress::compress_block_into;
+pub use crate::compress::compress_file;
+pub use crate::compress::compress_file_with_options;
+pub use crate::compress::write_skippable_frame;
+pub use crate::compress::compress_stream;
+pub use crate::decompress::DecompressOptions;
+pub use crate::decompress::FrameInfo;
+pub use crate::decompress::decompress;
+pub use crate::decompress::decompress_with_options;
+pub use crate::decompress::decompress_to_vec;
+pub use crate::decompress::decompress_with_skippable_frames;
+pub use crate::decompress::decompress_with_frame_info;
+pub use crate::decompress::is_skippable_magic;
+pub use crate::decompress::decompress_block;
+pub use crate::decompress::decompress_block_with_dictionary;
+pub use crate::decompress::decompress_block_into;
+pub use crate::decompress::decompress_block_partial;
+pub use crate::decompress::decompress_file;
+pub use crate::decompress::decompress_stream;
+pub use crate::frame_decoder::FrameDecoder;
+pub use crate::frame_encoder::FrameEncoder;
@@ -1,15 +1,19 @@
-use std::env;
+#![allow(clippy::needless_return)]
 
-use crate::xxhash32::xxhash32_file;
-use crate::xxhash32::xxhash32_stream;
-use crate::compress::compress_stream;
-use crate::compress::compress_file;
-use crate::decompress::decompress_stream;
-use crate::decompress::decompress_file;
+use std::env;
+use std::fs::File;
+use std::io::sink;
 
-mod xxhash32;
-mod compress;
-mod decompress;
+use lz4_simple::xxhash32_file;
+use lz4_simple::xxhash32_stream;
+use lz4_simple::compress_with_options;
+use lz4_simple::compress_file_with_options;
+use lz4_simple::CompressOptions;
+use lz4_simple::decompress_stream;
+use lz4_simple::decompress_file;
+use lz4_simple::decompress_with_frame_info;
+use lz4_simple::DecompressOptions;
+use lz4_simple::Error;
 
 // See http://fastcompression.blogspot.com/2011/05/lz4-explained.html
 // compile optimized:
@@ -19,6 +23,17 @@ mod decompress;
 // time target/release/lz4_simple -d ${IN} ${OUT}
 // time lz4 -d -f ${IN} ${OUT}
 
+// The compression options for "-1" .. "-9" and "--fast=N".
+fn compress_options(arg: &str) -> Option<CompressOptions> {
+    if let Some(acceleration) = arg.strip_prefix("--fast=") {
+        let mut options = CompressOptions::new(1);
+        options.acceleration = acceleration.parse().ok().filter(|x| *x >= 1)?;
+        return Some(options);
+    }
+    let level = arg.strip_prefix('-')?.parse().ok().filter(|x| (1..=9).contains(x))?;
+    return Some(CompressOptions::new(level));
+}
+
 pub fn main() {
     let args: Vec<String> = env::args().collect();
     let len = env::args().len();
@@ -27,7 +42,7 @@ pub fn main() {
         if len == 4 {
             let input_file_name = &args[2];
             let output_file_name = &args[3];
-            let result = decompress_file(&input_file_name, &output_file_name);
+            let result = decompress_file(input_file_name, output_file_name);
             match result {
                 Ok(bytes) => {
                     println!("Decompressed {bytes} bytes");
@@ -48,14 +63,12 @@ pub fn main() {
                 }
             };
         }
-    } else if len > 2 && (args[1] == "-1" || args[1] == "-2" || args[1] == "-3"
-        || args[1] == "-4" || args[1] == "-5" || args[1] == "-6"
-        || args[1] == "-7" || args[1] == "-8" || args[1] == "-9")  {
-        let level: usize = args[1].chars().nth(1).unwrap() as usize - '0' as usize;
+    } else if len > 2 && compress_options(&args[1]).is_some() {
+        let options = compress_options(&args[1]).unwrap();
         if len == 4 {
             let input_file_name = &args[2];
             let output_file_name = &args[3];
-            let result = compress_file(&input_file_name, &output_file_name, level);
+            let result = compress_file_with_options(input_file_name, output_file_name, &options);
             match result {
                 Ok(bytes) => {
                     println!("Compressed {bytes} bytes");
@@ -66,7 +79,7 @@ pub fn main() {
                 }
             };
         } else if len == 3 && args[2] == "-" {
-            let result = compress_stream(level);
+            let result = compress_with_options(std::io::stdin(), std::io::stdout(), &options);
             match result {
                 Ok(_bytes) => {
                     success = true;
@@ -76,6 +89,24 @@ pub fn main() {
                 }
             };
         }
+    } else if len == 3 && args[1] == "-l" {
+        let input_file_name = &args[2];
+        let result = File::open(input_file_name).map_err(Error::from).and_then(|in_file| {
+            return decompress_with_frame_info(in_file, sink(), &DecompressOptions::new());
+        });
+        match result {
+            Ok(frames) => {
+                println!("Frame  Magic     Blocks  Compressed  Decompressed");
+                for (i, frame) in frames.iter().enumerate() {
+                    println!("{:5}  {:08x}  {:6}  {:10}  {:12}", i, frame.magic,
+                        frame.block_count, frame.compressed_size, frame.decompressed_size);
+                }
+                success = true;
+            },
+            Err(e) => {
+                eprintln!("Failed to list {input_file_name}: {e}");
+            }
+        };
     } else if len == 3 && args[1] == "-h" {
         if args[2] == "-" {
             let result = xxhash32_stream();
@@ -90,7 +121,7 @@ pub fn main() {
             };
         } else {
             let input_file_name = &args[2];
-            let result = xxhash32_file(&input_file_name);
+            let result = xxhash32_file(input_file_name);
             match result {
                 Ok(hash) => {
                     println!("{:08x}", hash);
@@ -105,7 +136,9 @@ pub fn main() {
     if !success {
         eprintln!("Usage:");
         eprintln!("  lz4_simple [-1 .. -9] <input> <output>   Compress (1 fast,... 9 slow)");
+        eprintln!("  lz4_simple --fast=N   <input> <output>   Compress with acceleration N (larger N compresses less, and is often faster)");
         eprintln!("  lz4_simple -d         <input> <output>   Decompress");
+        eprintln!("  lz4_simple -l         <input>            List the frames");
         eprintln!("  lz4_simple -h         <input>            Calculate the XXHash32 checksum");
         eprintln!("Use '-' instead of <input> <output> to read from standard input and write to standard output");
     }
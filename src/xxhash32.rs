@@ -1,14 +1,13 @@
+use std::cmp::min;
 use std::io::prelude::*;
 use std::io::BufReader;
 use std::fs::File;
-use std::io::Error;
+use std::io;
 use std::io::ErrorKind;
 
-pub fn error(message: &str) -> Result<usize, Error> {
-    return Err(Error::new(ErrorKind::Other, message));
-}
+use crate::error::Error;
 
-pub fn read_fully<R: Read>(mut read: R, buffer: &mut [u8]) -> Result<usize, Error> {
+pub(crate) fn read_fully<R: Read>(mut read: R, buffer: &mut [u8]) -> Result<usize, io::Error> {
     let mut count: usize = 0;
     loop {
         let r = read.read(&mut buffer[count..]);
@@ -30,22 +29,19 @@ pub fn read_fully<R: Read>(mut read: R, buffer: &mut [u8]) -> Result<usize, Erro
     }
 }
 
-pub fn read_vec_u32_le(data: &Vec<u8>, pos: usize) -> u32 {
-    let d: [u8; 4] = data[pos..pos + 4].try_into().unwrap();
-    return (d[0] as u32) |
-        ((d[1] as u32) << 8) |
-        ((d[2] as u32) << 16) |
-        ((d[3] as u32) << 24);
-}
-
-pub fn write_vec_u32_le(data: &mut Vec<u8>, pos: usize, x: u32) {
+pub(crate) fn write_u32_le(data: &mut [u8], pos: usize, x: u32) {
     data[pos] = x as u8;
     data[pos + 1] = (x >> 8) as u8;
     data[pos + 2] = (x >> 16) as u8;
     data[pos + 3] = (x >> 24) as u8;
 }
 
-pub fn read_u32_le(data: &[u8], pos: usize) -> u32 {
+pub(crate) fn write_u64_le(data: &mut [u8], pos: usize, x: u64) {
+    write_u32_le(data, pos, x as u32);
+    write_u32_le(data, pos + 4, (x >> 32) as u32);
+}
+
+pub(crate) fn read_u32_le(data: &[u8], pos: usize) -> u32 {
     let d: [u8; 4] = data[pos..pos + 4].try_into().unwrap();
     return (d[0] as u32) |
         ((d[1] as u32) << 8) |
@@ -53,7 +49,7 @@ pub fn read_u32_le(data: &[u8], pos: usize) -> u32 {
         ((d[3] as u32) << 24);
 }
 
-pub fn read_u64_le(data: &[u8], pos: usize) -> u64 {
+pub(crate) fn read_u64_le(data: &[u8], pos: usize) -> u64 {
     let d: [u8; 8] = data[pos..pos + 8].try_into().unwrap();
     return (d[0] as u64) |
         ((d[1] as u64) << 8) |
@@ -74,7 +70,8 @@ pub fn xxhash32_file(input_file_name: &str) -> Result<u32, Error> {
     return xxhash32(in_file);
 }
 
-fn xxhash32<R: Read>(read: R) -> Result<u32, Error> {
+/// Calculate the XXHash32 checksum (seed 0) of everything that can be read.
+pub fn xxhash32<R: Read>(read: R) -> Result<u32, Error> {
     let mut reader = BufReader::new(read);
     let mut block: Vec<u8> = Vec::new();
     let block_size = 4 * 1024 * 1024;
@@ -86,7 +83,7 @@ fn xxhash32<R: Read>(read: R) -> Result<u32, Error> {
         if read == 0 {
             break;
         }
-        hash = state.update(&block, 0, read)?;
+        hash = state.update(&block[0..read]);
     }
     return Ok(hash);
 }
@@ -102,6 +99,8 @@ pub struct XXHash32 {
     v2: u32,
     v3: u32,
     v4: u32,
+    mem: [u8; 16],
+    mem_len: usize,
     total: usize
 }
 
@@ -112,14 +111,32 @@ impl XXHash32 {
             v2: seed.wrapping_add(PRIME2),
             v3: seed,
             v4: seed.wrapping_sub(PRIME1),
+            mem: [0; 16],
+            mem_len: 0,
             total: 0
         }
     }
 
-    pub fn update(&mut self, buf: &Vec<u8>, start: usize, len: usize) -> Result<u32, Error> {
-        let end = start + len;
-        let mut pos = start;
-        if len >= 16 {
+    /// Hash the data. The data may be passed in chunks of any size.
+    /// Returns the hash of all data so far.
+    pub fn update(&mut self, buf: &[u8]) -> u32 {
+        let len = buf.len();
+        let end = len;
+        self.total += len;
+        let mut pos = 0;
+        if self.mem_len > 0 {
+            let fill = min(16 - self.mem_len, len);
+            self.mem[self.mem_len..self.mem_len + fill].copy_from_slice(&buf[pos..pos + fill]);
+            self.mem_len += fill;
+            pos += fill;
+            if self.mem_len < 16 {
+                return self.digest();
+            }
+            let sb = self.mem;
+            self.round(&sb);
+            self.mem_len = 0;
+        }
+        if pos + 16 <= end {
             let limit = end - 16;
             let mut v1 = self.v1;
             let mut v2 = self.v2;
@@ -149,11 +166,29 @@ impl XXHash32 {
             self.v3 = v3;
             self.v4 = v4;
         }
+        self.mem_len = end - pos;
+        self.mem[0..self.mem_len].copy_from_slice(&buf[pos..end]);
+        return self.digest();
+    }
+
+    fn round(&mut self, sb: &[u8; 16]) {
+        self.v1 = read_u32_le(sb, 0).
+            wrapping_mul(PRIME2).wrapping_add(self.v1).
+            rotate_left(13).wrapping_mul(PRIME1);
+        self.v2 = read_u32_le(sb, 4).
+            wrapping_mul(PRIME2).wrapping_add(self.v2).
+            rotate_left(13).wrapping_mul(PRIME1);
+        self.v3 = read_u32_le(sb, 8).
+            wrapping_mul(PRIME2).wrapping_add(self.v3).
+            rotate_left(13).wrapping_mul(PRIME1);
+        self.v4 = read_u32_le(sb, 12).
+            wrapping_mul(PRIME2).wrapping_add(self.v4).
+            rotate_left(13).wrapping_mul(PRIME1);
+    }
+
+    /// The hash of all data passed to update so far.
+    pub fn digest(&self) -> u32 {
         let mut h32: u32;
-        if self.total & 0xf != 0 {
-            return Err(Error::new(ErrorKind::Other, "Wrong call sequence"));
-        }
-        self.total += len;
         if self.total >= 16 {
             h32 = self.v1.rotate_left(1).
                 wrapping_add(self.v2.rotate_left(7)).
@@ -163,21 +198,22 @@ impl XXHash32 {
             h32 = self.v3.wrapping_add(PRIME5);
         }
         h32 = h32.wrapping_add(self.total as u32);
-        while pos + 4 <= end {
-            h32 = read_vec_u32_le(buf, pos).
+        let mut pos = 0;
+        while pos + 4 <= self.mem_len {
+            h32 = read_u32_le(&self.mem, pos).
                 wrapping_mul(PRIME3).wrapping_add(h32).
                 rotate_left(17).wrapping_mul(PRIME4);
             pos += 4;
         }
-        while pos < end {
-            h32 = (buf[pos] as u32).
+        while pos < self.mem_len {
+            h32 = (self.mem[pos] as u32).
                 wrapping_mul(PRIME5).wrapping_add(h32).
                 rotate_left(11).wrapping_mul(PRIME1);
             pos += 1;
         }
         h32 = (h32 ^ (h32 >> 15)).wrapping_mul(PRIME2);
         h32 = (h32 ^ (h32 >> 13)).wrapping_mul(PRIME3);
-        return Ok(h32 ^ (h32 >> 16));
+        return h32 ^ (h32 >> 16);
     }
 }
 
@@ -188,19 +224,34 @@ mod tests {
     #[test]
     fn hello_world() {
         let mut hash = XXHash32::new(0);
-        let result = hash.update(&"Hello world".as_bytes().to_vec(), 0, 11);
-        assert_eq!(0x9705d437, result.unwrap());
+        let result = hash.update("Hello world".as_bytes());
+        assert_eq!(0x9705d437, result);
     }
 
     #[test]
-    fn read_vec_u32_le_test() {
-        let mut x: Vec<u8> = Vec::new();
-        x.resize(4, 0);
-        x[0] = 0x12;
-        x[1] = 0x34;
-        x[2] = 0x56;
-        x[3] = 0x78;
-        assert_eq!(0x78563412, read_vec_u32_le(&x, 0));
+    fn update_in_chunks() {
+        let mut data: Vec<u8> = Vec::new();
+        for i in 0..1000 {
+            data.push((i * 7 + i / 13) as u8);
+        }
+        let expected = XXHash32::new(0).update(&data);
+        for chunk in [1, 3, 15, 16, 17, 100] {
+            let mut hash = XXHash32::new(0);
+            let mut pos = 0;
+            while pos < data.len() {
+                let len = min(chunk, data.len() - pos);
+                hash.update(&data[pos..pos + len]);
+                pos += len;
+            }
+            assert_eq!(expected, hash.digest(), "chunk {chunk}");
+        }
+    }
+
+    #[test]
+    fn write_u32_le_test() {
+        let mut x: [u8; 4] = [0; 4];
+        write_u32_le(&mut x, 0, 0x78563412);
+        assert_eq!([0x12, 0x34, 0x56, 0x78], x);
     }
 
     #[test]